@@ -0,0 +1,176 @@
+//! Helpers shared by the integration tests, each test crate declares `mod common;`.
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, FrameSink, FrameStream};
+use rsocket_rust::{stream, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+/// Echoes the request after 300ms for "late", never for "slow" and at once otherwise.
+async fn respond(req: Payload) -> Result<Option<Payload>> {
+    match req.data_utf8() {
+        Some("slow") => futures::future::pending::<()>().await,
+        Some("late") => tokio::time::sleep(Duration::from_millis(300)).await,
+        _ => (),
+    }
+    Ok(Some(req))
+}
+
+/// A responder whose streams emit a payload every 50ms, `None` means infinite.
+pub struct TickRSocket(pub Option<usize>);
+
+#[rsocket_rust::async_trait]
+impl RSocket for TickRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        respond(req).await
+    }
+
+    fn request_stream(&self, _req: Payload) -> Flux<Result<Payload>> {
+        let n = self.0.unwrap_or(usize::MAX);
+        Box::pin(stream! {
+            for i in 0..n {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                yield Ok(Payload::builder().set_data_utf8(&format!("#{}", i)).build());
+            }
+        })
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        reqs
+    }
+}
+
+/// A responder whose streams emit as many payloads as the request's data says, one every
+/// 100ms, and never emit anything otherwise.
+pub struct SlowRSocket;
+
+#[rsocket_rust::async_trait]
+impl RSocket for SlowRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        respond(req).await
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        let count = req.data_utf8().and_then(|it| it.parse::<usize>().ok());
+        Box::pin(stream! {
+            let n = match count {
+                Some(n) => n,
+                None => futures::future::pending().await,
+            };
+            for i in 0..n {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                yield Ok(Payload::builder().set_data_utf8(&format!("#{}", i)).build());
+            }
+        })
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        reqs
+    }
+}
+
+/// Connects to the server and sends SETUP, starting the server with the acceptor first unless
+/// it is `None`.
+pub async fn connect_raw(
+    addr: &'static str,
+    acceptor: Option<ServerResponder>,
+) -> (Box<FrameSink>, Box<FrameStream>) {
+    if let Some(acceptor) = acceptor {
+        tokio::spawn(async move {
+            RSocketFactory::receive()
+                .transport(TcpServerTransport::from(addr))
+                .acceptor(acceptor)
+                .serve()
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, stream) = conn.split();
+    sink.send(frame::Setup::builder(0, 0).build())
+        .await
+        .unwrap();
+    (sink, stream)
+}
+
+/// Waits for the next frame, `None` means the connection is closed.
+pub async fn next_or_closed(stream: &mut Box<FrameStream>) -> Option<Frame> {
+    tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .expect("should receive next frame")
+        .map(|it| it.unwrap())
+}
+
+pub async fn next_frame(stream: &mut Box<FrameStream>) -> Frame {
+    next_or_closed(stream)
+        .await
+        .expect("should receive next frame")
+}
+
+/// Expects an ERROR[INVALID] on the stream.
+pub async fn expect_invalid(stream: &mut Box<FrameStream>, sid: u32) {
+    let next = next_frame(stream).await;
+    assert_eq!(sid, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_INVALID, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+}
+
+pub fn rsocket_error<T>(res: Result<T>) -> RSocketError {
+    match res {
+        Ok(_) => panic!("should fail"),
+        Err(e) => e.downcast::<RSocketError>().unwrap(),
+    }
+}
+
+pub fn is_rejected<T>(res: Result<T>) -> bool {
+    match res {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.downcast_ref::<RSocketError>(),
+            Some(RSocketError::RequestRejected(_))
+        ),
+    }
+}
+
+pub fn is_timeout<T>(res: Result<T>) -> bool {
+    match res {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.downcast_ref::<RSocketError>(),
+            Some(RSocketError::RequestTimeout(_))
+        ),
+    }
+}
+
+pub fn is_closed<T>(res: Result<T>) -> bool {
+    match res {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.downcast_ref::<RSocketError>(),
+            Some(RSocketError::ConnectionClosed(_))
+        ),
+    }
+}
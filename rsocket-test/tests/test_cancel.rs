@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Connection;
+use rsocket_rust::{stream, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::mpsc;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts a raw server which answers each request with one payload and forwards all
+/// received frames.
+async fn serve_raw(addr: &'static str) -> mpsc::UnboundedReceiver<Frame> {
+    let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+    tokio::spawn(async move {
+        let tp = server.next().await.unwrap().unwrap();
+        let (mut sink, mut stream) = tp.connect().await.unwrap().split();
+        while let Some(Ok(next)) = stream.next().await {
+            if let Body::RequestStream(_) = next.get_body_ref() {
+                let sending = frame::Payload::builder(next.get_stream_id(), Frame::FLAG_NEXT)
+                    .set_data("foobar".into())
+                    .build();
+                sink.send(sending).await.unwrap();
+            }
+            frames_tx.send(next).unwrap();
+        }
+    });
+    frames_rx
+}
+
+/// Signals when the responder's work has been dropped.
+struct DropNotify(mpsc::UnboundedSender<&'static str>, &'static str);
+
+impl Drop for DropNotify {
+    fn drop(&mut self) {
+        let _ = self.0.send(self.1);
+    }
+}
+
+/// A responder which never finishes its work.
+struct PendingRSocket(mpsc::UnboundedSender<&'static str>);
+
+#[rsocket_rust::async_trait]
+impl RSocket for PendingRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, _req: Payload) -> Result<Option<Payload>> {
+        let _notify = DropNotify(self.0.clone(), "request_response");
+        futures::future::pending::<()>().await;
+        Ok(None)
+    }
+
+    fn request_stream(&self, _req: Payload) -> Flux<Result<Payload>> {
+        let notify = DropNotify(self.0.clone(), "request_stream");
+        Box::pin(stream! {
+            let _notify = notify;
+            yield Ok(Payload::from("foobar"));
+            futures::future::pending::<()>().await;
+        })
+    }
+
+    fn request_channel(&self, _reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        let notify = DropNotify(self.0.clone(), "request_channel");
+        Box::pin(stream! {
+            let _notify = notify;
+            yield Ok(Payload::from("foobar"));
+            futures::future::pending::<()>().await;
+        })
+    }
+}
+
+async fn serve_pending(addr: &'static str) -> mpsc::UnboundedReceiver<&'static str> {
+    let (dropped_tx, dropped_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(move |_setup, _socket| {
+                Ok(Box::new(PendingRSocket(dropped_tx.clone())))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    dropped_rx
+}
+
+async fn wait_for_drop(dropped: &mut mpsc::UnboundedReceiver<&'static str>) -> &'static str {
+    tokio::time::timeout(Duration::from_secs(3), dropped.recv())
+        .await
+        .expect("responder should be aborted")
+        .unwrap()
+}
+
+async fn wait_for_cancel(frames: &mut mpsc::UnboundedReceiver<Frame>) -> u32 {
+    loop {
+        let next = tokio::time::timeout(Duration::from_secs(3), frames.recv())
+            .await
+            .expect("should receive CANCEL")
+            .unwrap();
+        if let Body::Cancel() = next.get_body_ref() {
+            return next.get_stream_id();
+        }
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_cancel_dropped_stream() {
+    init();
+    let addr = "127.0.0.1:7811";
+    let mut frames = serve_raw(addr).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+
+    let mut results = cli.request_stream(Payload::from("hello"));
+    let first = results.next().await.unwrap().unwrap();
+    assert_eq!(Some("foobar"), first.data_utf8());
+    drop(results);
+    assert_eq!(1, wait_for_cancel(&mut frames).await);
+}
+
+#[tokio::main]
+#[test]
+async fn test_cancel_request_response_on_timeout() {
+    init();
+    let addr = "127.0.0.1:7812";
+    let mut frames = serve_raw(addr).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+
+    let res = tokio::time::timeout(
+        Duration::from_millis(100),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(1, wait_for_cancel(&mut frames).await);
+}
+
+#[tokio::main]
+#[test]
+async fn test_cancel_aborts_responder() {
+    init();
+    let addr = "127.0.0.1:7813";
+    let mut dropped = serve_pending(addr).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+
+    let res = tokio::time::timeout(
+        Duration::from_millis(100),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!("request_response", wait_for_drop(&mut dropped).await);
+
+    let mut results = cli.request_stream(Payload::from("hello"));
+    assert!(results.next().await.unwrap().is_ok());
+    drop(results);
+    assert_eq!("request_stream", wait_for_drop(&mut dropped).await);
+
+    let mut results = cli.request_channel(Box::pin(stream! {
+        yield Ok(Payload::from("hello"));
+        futures::future::pending::<()>().await;
+    }));
+    assert!(results.next().await.unwrap().is_ok());
+    drop(results);
+    assert_eq!("request_channel", wait_for_drop(&mut dropped).await);
+}
+
+#[tokio::main]
+#[test]
+async fn test_disconnect_aborts_responders() {
+    init();
+    let addr = "127.0.0.1:7814";
+    let mut dropped = serve_pending(addr).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(frame::Setup::builder(0, 0).build())
+        .await
+        .unwrap();
+    sink.send(frame::RequestResponse::builder(1, 0).build())
+        .await
+        .unwrap();
+    sink.send(frame::RequestStream::builder(3, 0).build())
+        .await
+        .unwrap();
+    assert_eq!(3, stream.next().await.unwrap().unwrap().get_stream_id());
+
+    // the responders are aborted once the transport is gone, not only on CANCEL.
+    drop(sink);
+    drop(stream);
+    let mut aborted = vec![
+        wait_for_drop(&mut dropped).await,
+        wait_for_drop(&mut dropped).await,
+    ];
+    aborted.sort_unstable();
+    assert_eq!(vec!["request_response", "request_stream"], aborted);
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::error::RSocketError;
+use rsocket_rust::prelude::*;
+use rsocket_rust::{stream, Client, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::{mpsc, Notify};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+type ChannelFn = Box<dyn Fn(Flux<Result<Payload>>) -> Flux<Result<Payload>> + Send + Sync>;
+
+/// A responder which answers REQUEST_CHANNEL with the given function.
+struct ChannelRSocket(Arc<ChannelFn>);
+
+#[rsocket_rust::async_trait]
+impl RSocket for ChannelRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        Ok(Some(req))
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        Box::pin(futures::stream::iter(vec![Ok(req)]))
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        (self.0)(reqs)
+    }
+}
+
+async fn serve_channel(addr: &'static str, f: ChannelFn) -> Client {
+    let f = Arc::new(f);
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .limit_rate(4, 1)
+            .acceptor(Box::new(move |_setup, _socket| {
+                Ok(Box::new(ChannelRSocket(f.clone())))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .limit_rate(4, 1)
+        .start()
+        .await
+        .unwrap()
+}
+
+async fn next_utf8(results: &mut Flux<Result<Payload>>) -> Option<String> {
+    tokio::time::timeout(Duration::from_secs(3), results.next())
+        .await
+        .expect("should receive next payload")
+        .map(|it| it.unwrap().data_utf8().unwrap().to_owned())
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_with_request_n() {
+    init();
+    let cli = serve_channel("127.0.0.1:7821", Box::new(|reqs| reqs)).await;
+
+    let reqs = futures::stream::iter(
+        (0..100).map(|i| Ok(Payload::builder().set_data_utf8(&format!("#{}", i)).build())),
+    );
+    let mut results = cli.request_channel(Box::pin(reqs));
+    for i in 0..100 {
+        assert_eq!(Some(format!("#{}", i)), next_utf8(&mut results).await);
+    }
+    assert_eq!(None, next_utf8(&mut results).await);
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_responder_keeps_sending_after_requester_completes() {
+    init();
+    let cli = serve_channel(
+        "127.0.0.1:7822",
+        Box::new(|mut reqs| {
+            Box::pin(stream! {
+                let mut n = 0;
+                while let Some(Ok(_)) = reqs.next().await {
+                    n += 1;
+                }
+                yield Ok(Payload::builder().set_data_utf8(&format!("received {}", n)).build());
+                yield Ok(Payload::from("bye"));
+            })
+        }),
+    )
+    .await;
+
+    let reqs = futures::stream::iter(vec![Ok(Payload::from("a")), Ok(Payload::from("b"))]);
+    let mut results = cli.request_channel(Box::pin(reqs));
+    assert_eq!(Some("received 2".into()), next_utf8(&mut results).await);
+    assert_eq!(Some("bye".into()), next_utf8(&mut results).await);
+    assert_eq!(None, next_utf8(&mut results).await);
+
+    let mut results = cli.request_channel(Box::pin(futures::stream::empty()));
+    assert_eq!(Some("received 0".into()), next_utf8(&mut results).await);
+    assert_eq!(Some("bye".into()), next_utf8(&mut results).await);
+    assert_eq!(None, next_utf8(&mut results).await);
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_requester_keeps_sending_after_responder_completes() {
+    init();
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+    let cli = serve_channel(
+        "127.0.0.1:7823",
+        Box::new(move |mut reqs| {
+            let received_tx = received_tx.clone();
+            tokio::spawn(async move {
+                while let Some(Ok(next)) = reqs.next().await {
+                    received_tx
+                        .send(next.data_utf8().unwrap().to_owned())
+                        .unwrap();
+                }
+                received_tx.send("completed".into()).unwrap();
+            });
+            Box::pin(futures::stream::empty())
+        }),
+    )
+    .await;
+
+    let responded = Arc::new(Notify::new());
+    let notified = responded.clone();
+    let reqs = stream! {
+        yield Ok(Payload::from("a"));
+        notified.notified().await;
+        yield Ok(Payload::from("b"));
+    };
+    let mut results = cli.request_channel(Box::pin(reqs));
+    assert_eq!(None, next_utf8(&mut results).await);
+    responded.notify_one();
+
+    for expect in &["a", "b", "completed"] {
+        let next = tokio::time::timeout(Duration::from_secs(3), received_rx.recv())
+            .await
+            .expect("should receive from requester");
+        assert_eq!(Some(expect.to_string()), next);
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_requester_cancels_inbound_only() {
+    init();
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+    let cli = serve_channel(
+        "127.0.0.1:7824",
+        Box::new(move |mut reqs| {
+            let received_tx = received_tx.clone();
+            tokio::spawn(async move {
+                while let Some(Ok(next)) = reqs.next().await {
+                    received_tx
+                        .send(next.data_utf8().unwrap().to_owned())
+                        .unwrap();
+                }
+                received_tx.send("completed".into()).unwrap();
+            });
+            Box::pin(stream! {
+                loop {
+                    yield Ok(Payload::from("tick"));
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        }),
+    )
+    .await;
+
+    let cancelled = Arc::new(Notify::new());
+    let notified = cancelled.clone();
+    let reqs = stream! {
+        yield Ok(Payload::from("a"));
+        notified.notified().await;
+        yield Ok(Payload::from("b"));
+    };
+    let mut results = cli.request_channel(Box::pin(reqs));
+    assert_eq!(Some("tick".into()), next_utf8(&mut results).await);
+    drop(results);
+    cancelled.notify_one();
+
+    for expect in &["a", "b", "completed"] {
+        let next = tokio::time::timeout(Duration::from_secs(3), received_rx.recv())
+            .await
+            .expect("should receive from requester");
+        assert_eq!(Some(expect.to_string()), next);
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_error_terminates_both_directions() {
+    init();
+    let cli = serve_channel(
+        "127.0.0.1:7825",
+        Box::new(|_reqs| {
+            Box::pin(stream! {
+                yield Ok(Payload::from("a"));
+                yield Err(RSocketError::ApplicationException("oops".into()).into());
+            })
+        }),
+    )
+    .await;
+
+    let reqs = stream! {
+        loop {
+            yield Ok(Payload::from("ping"));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    let mut results = cli.request_channel(Box::pin(reqs));
+    assert_eq!(Some("a".into()), next_utf8(&mut results).await);
+    let next = tokio::time::timeout(Duration::from_secs(3), results.next())
+        .await
+        .unwrap();
+    assert!(next.unwrap().is_err());
+    let next = tokio::time::timeout(Duration::from_secs(3), results.next())
+        .await
+        .unwrap();
+    assert!(next.is_none());
+
+    // the connection is still alive.
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::error;
+use rsocket_rust::frame::Body;
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, ServerTransport, Transport};
+use rsocket_rust::Client;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::mpsc;
+
+use common::{is_closed, TickRSocket};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+async fn serve_tick(addr: &'static str, n: Option<usize>) -> Client {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(move |_setup, _socket| {
+                Ok(Box::new(TickRSocket(n)))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap()
+}
+
+#[tokio::main]
+#[test]
+async fn test_close_gracefully_drains_streams() {
+    init();
+    let cli = serve_tick("127.0.0.1:7861", Some(5)).await;
+
+    let mut results = cli.request_stream(Payload::from("hello"));
+    let consumer = tokio::spawn(async move {
+        let mut received = vec![];
+        while let Some(next) = results.next().await {
+            received.push(next.unwrap().data_utf8().unwrap().to_owned());
+        }
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.close_gracefully(Duration::from_secs(2)),
+    )
+    .await
+    .expect("should be closed");
+
+    let received = consumer.await.unwrap();
+    assert_eq!(vec!["#0", "#1", "#2", "#3", "#4"], received);
+    assert!(is_closed(
+        cli.request_response(Payload::from("hello")).await
+    ));
+}
+
+#[tokio::main]
+#[test]
+async fn test_close_gracefully_cancels_at_deadline() {
+    init();
+    let cli = serve_tick("127.0.0.1:7862", None).await;
+
+    let mut results = cli.request_stream(Payload::from("hello"));
+    assert!(results.next().await.unwrap().is_ok());
+
+    tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.close_gracefully(Duration::from_millis(200)),
+    )
+    .await
+    .expect("should be closed");
+
+    let mut last = None;
+    while let Some(next) = results.next().await {
+        last = Some(next);
+    }
+    assert!(is_closed(last.unwrap()));
+}
+
+#[tokio::main]
+#[test]
+async fn test_close_sends_connection_close() {
+    init();
+    let addr = "127.0.0.1:7863";
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+    let connecting = tokio::spawn(async move {
+        RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .on_close_with(Box::new(move |reason| {
+                closed_tx.send(reason.is_none()).unwrap();
+            }))
+            .start()
+            .await
+            .unwrap()
+    });
+    let tp = server.next().await.unwrap().unwrap();
+    let (_sink, mut stream) = tp.connect().await.unwrap().split();
+    let cli = connecting.await.unwrap();
+
+    let setup = stream.next().await.unwrap().unwrap();
+    assert!(matches!(setup.get_body_ref(), Body::Setup(_)));
+
+    tokio::time::timeout(Duration::from_secs(3), cli.close())
+        .await
+        .expect("should be closed");
+    assert_eq!(Some(true), closed_rx.recv().await);
+
+    let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(0, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_CLOSED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap();
+    assert!(next.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_drop_client_fires_on_close() {
+    init();
+    let addr = "127.0.0.1:7864";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(TickRSocket(None)))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .on_close_with(Box::new(move |reason| {
+            closed_tx.send(reason.is_none()).unwrap();
+        }))
+        .start()
+        .await
+        .unwrap();
+    assert!(cli.request_response(Payload::from("hello")).await.is_ok());
+
+    drop(cli);
+    let closed = tokio::time::timeout(Duration::from_secs(3), closed_rx.recv())
+        .await
+        .expect("should be closed");
+    assert_eq!(Some(true), closed);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::{connect_raw, is_rejected, next_frame, SlowRSocket};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+async fn serve_slow(addr: &'static str, max_streams: Option<usize>) {
+    tokio::spawn(async move {
+        let mut server = RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket))));
+        if let Some(max) = max_streams {
+            server = server.max_concurrent_streams(max);
+        }
+        server.serve().await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+fn request(sid: u32, data: &'static str) -> Frame {
+    frame::RequestResponse::builder(sid, 0)
+        .set_data(Bytes::from(data))
+        .build()
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_streams_beyond_limit() {
+    init();
+    let addr = "127.0.0.1:7901";
+    serve_slow(addr, Some(1)).await;
+
+    let (mut sink, mut stream) = connect_raw(addr, None).await;
+    sink.send(request(1, "slow")).await.unwrap();
+    sink.send(request(3, "hello")).await.unwrap();
+    let next = next_frame(&mut stream).await;
+    assert_eq!(3, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_REJECTED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+
+    // the permit is released once the in-flight stream terminates.
+    sink.send(frame::Cancel::builder(1, 0).build())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    sink.send(request(5, "hello")).await.unwrap();
+    let next = next_frame(&mut stream).await;
+    assert_eq!(5, next.get_stream_id());
+    assert!(matches!(next.get_body_ref(), Body::Payload(_)));
+}
+
+#[tokio::main]
+#[test]
+async fn test_requests_beyond_limit_fail_fast() {
+    init();
+    let addr = "127.0.0.1:7902";
+    serve_slow(addr, None).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .max_concurrent_requests(1, RequestOverflow::Reject)
+        .start()
+        .await
+        .unwrap();
+    let mut results = cli.request_stream(Payload::from("3"));
+    assert!(results.next().await.unwrap().is_ok());
+    assert!(is_rejected(
+        cli.request_response(Payload::from("hello")).await
+    ));
+
+    // the permit is released once the stream completes.
+    while results.next().await.is_some() {}
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_requests_beyond_limit_are_queued() {
+    init();
+    let addr = "127.0.0.1:7903";
+    serve_slow(addr, None).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .max_concurrent_requests(1, RequestOverflow::Queue)
+        .start()
+        .await
+        .unwrap();
+    let mut results = cli.request_stream(Payload::from("3"));
+    assert!(results.next().await.unwrap().is_ok());
+
+    // the request waits until the stream completes.
+    let res = tokio::time::timeout(
+        Duration::from_millis(100),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await;
+    assert!(res.is_err());
+
+    while results.next().await.is_some() {}
+    let res = tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await
+    .expect("should be sent once the stream completes")
+    .unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::frame::{self, Body};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, ServerTransport, Transport};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::mpsc;
+
+use common::rsocket_error;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+#[tokio::main]
+#[test]
+async fn test_connection_error_fails_outstanding_requests() {
+    init();
+    let addr = "127.0.0.1:7951";
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+    let connecting = tokio::spawn(async move {
+        RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .on_close_with(Box::new(move |reason| {
+                let code = reason.and_then(|it| it.code());
+                closed_tx.send(code).unwrap();
+            }))
+            .start()
+            .await
+            .unwrap()
+    });
+    let tp = server.next().await.unwrap().unwrap();
+    let (mut sink, mut stream) = tp.connect().await.unwrap().split();
+    let cli = connecting.await.unwrap();
+
+    let requesting = {
+        let cli = cli.clone();
+        tokio::spawn(async move { cli.request_response(Payload::from("hello")).await })
+    };
+    let mut results = cli.request_stream(Payload::from("hello"));
+    let mut received = 0;
+    while received < 2 {
+        let next = stream.next().await.unwrap().unwrap();
+        if matches!(
+            next.get_body_ref(),
+            Body::RequestResponse(_) | Body::RequestStream(_)
+        ) {
+            received += 1;
+        }
+    }
+    sink.send(
+        frame::Error::builder(0, 0)
+            .set_code(error::ERR_CONN_FAILED)
+            .set_data(Bytes::from("broken"))
+            .build(),
+    )
+    .await
+    .unwrap();
+
+    let e = rsocket_error(
+        tokio::time::timeout(Duration::from_secs(3), requesting)
+            .await
+            .expect("should fail")
+            .unwrap(),
+    );
+    assert!(matches!(e, RSocketError::ConnectionException(_)));
+    let next = tokio::time::timeout(Duration::from_secs(3), results.next())
+        .await
+        .expect("should fail")
+        .unwrap();
+    assert!(matches!(
+        rsocket_error(next),
+        RSocketError::ConnectionException(_)
+    ));
+    assert_eq!(Some(Some(error::ERR_CONN_FAILED)), closed_rx.recv().await);
+
+    // requests sent afterwards fail with the same error.
+    let e = rsocket_error(cli.request_response(Payload::from("hello")).await);
+    assert_eq!(Some(&b"broken"[..]), e.data());
+}
+
+#[tokio::main]
+#[test]
+async fn test_rejected_setup_fails_first_request() {
+    init();
+    let addr = "127.0.0.1:7952";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| {
+                Err(RSocketError::RejectedSetup("go away".into()).into())
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+    let res = tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await
+    .expect("should fail");
+    let e = rsocket_error(res);
+    assert!(matches!(e, RSocketError::RejectedSetup(_)));
+    assert_eq!(Some(&b"go away"[..]), e.data());
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::prelude::*;
+use rsocket_rust::{stream, Client, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::rsocket_error;
+
+const ERR_RETRY_LATER: u32 = 0x0000_0301;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// A responder which fails every request with the error code in the data of the request.
+struct FailingRSocket;
+
+impl FailingRSocket {
+    fn error_of(req: &Payload) -> RSocketError {
+        let code = req.data_utf8().and_then(|s| s.parse().ok()).unwrap_or(0);
+        RSocketError::custom(code, &[0xC3, 0x28][..])
+    }
+}
+
+#[rsocket_rust::async_trait]
+impl RSocket for FailingRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        Err(Self::error_of(&req).into())
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        Box::pin(stream! {
+            yield Ok(Payload::from("first"));
+            yield Err(Self::error_of(&req).into());
+        })
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        reqs
+    }
+}
+
+async fn connect(addr: &'static str) -> Client {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(FailingRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap()
+}
+
+fn request(code: u32) -> Payload {
+    Payload::builder().set_data_utf8(&code.to_string()).build()
+}
+
+#[tokio::main]
+#[test]
+async fn test_custom_error_code() {
+    init();
+    let cli = connect("127.0.0.1:7941").await;
+
+    let e = rsocket_error(cli.request_response(request(ERR_RETRY_LATER)).await);
+    assert!(matches!(e, RSocketError::Custom(..)));
+    assert_eq!(Some(ERR_RETRY_LATER), e.code());
+    assert_eq!(Some(&[0xC3, 0x28][..]), e.data());
+
+    let mut results = cli.request_stream(request(error::ERR_CUSTOM_MAX));
+    assert!(results.next().await.unwrap().is_ok());
+    let e = rsocket_error(results.next().await.unwrap());
+    assert_eq!(Some(error::ERR_CUSTOM_MAX), e.code());
+    assert_eq!(Some(&[0xC3, 0x28][..]), e.data());
+    assert!(results.next().await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_custom_error_code_out_of_range() {
+    init();
+    let cli = connect("127.0.0.1:7942").await;
+
+    // codes out of the application range are sent as APPLICATION_ERROR.
+    let e = rsocket_error(cli.request_response(request(error::ERR_REJECTED)).await);
+    assert!(matches!(e, RSocketError::ApplicationException(_)));
+    assert_eq!(Some(error::ERR_APPLICATION), e.code());
+}
+
+#[tokio::main]
+#[test]
+async fn test_custom_error_of_channel_outbound() {
+    init();
+    let cli = connect("127.0.0.1:7943").await;
+
+    let reqs: Flux<Result<Payload>> = Box::pin(stream! {
+        yield Ok(Payload::from("hello"));
+        yield Err(RSocketError::custom(ERR_RETRY_LATER, "later").into());
+    });
+    // the inbound fails with the error of the outbound as it is.
+    let results: Vec<_> = cli.request_channel(reqs).collect().await;
+    let e = rsocket_error(results.into_iter().last().unwrap());
+    assert!(matches!(e, RSocketError::Custom(..)));
+    assert_eq!(Some(ERR_RETRY_LATER), e.code());
+    assert_eq!(Some(&b"later"[..]), e.data());
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::SinkExt;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{FrameSink, FrameStream};
+use rsocket_rust::utils::{EchoRSocket, Writeable};
+use rsocket_rust::Result;
+use rsocket_rust_transport_tcp::TcpServerTransport;
+use tokio::sync::mpsc;
+
+use common::{connect_raw, expect_invalid, next_frame};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts an echo server which reassembles payloads up to 16 bytes, then connects to it.
+async fn connect_echo(addr: &'static str) -> (Box<FrameSink>, Box<FrameStream>) {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .max_reassembled_size(16)
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    connect_raw(addr, None).await
+}
+
+async fn expect_echo(stream: &mut Box<FrameStream>, sid: u32, data: &str) {
+    let next = next_frame(stream).await;
+    assert_eq!(sid, next.get_stream_id());
+    match next.get_body() {
+        Body::Payload(p) => assert_eq!(Some(&Bytes::from(data.to_owned())), p.get_data()),
+        body => panic!("should receive PAYLOAD: {:?}", body),
+    }
+}
+
+fn fragment(sid: u32, data: &'static str, follow: bool) -> Frame {
+    let flag = if follow { Frame::FLAG_FOLLOW } else { 0 };
+    frame::Payload::builder(sid, flag)
+        .set_data(Bytes::from(data))
+        .build()
+}
+
+fn request(sid: u32, data: &'static str, follow: bool) -> Frame {
+    let flag = if follow { Frame::FLAG_FOLLOW } else { 0 };
+    frame::RequestResponse::builder(sid, flag)
+        .set_data(Bytes::from(data))
+        .build()
+}
+
+#[tokio::main]
+#[test]
+async fn test_reassemble_fragments() {
+    init();
+    let (mut sink, mut stream) = connect_echo("127.0.0.1:7921").await;
+
+    sink.send(request(1, "hello", true)).await.unwrap();
+    sink.send(fragment(1, "world", false)).await.unwrap();
+    expect_echo(&mut stream, 1, "helloworld").await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_oversized_fragments() {
+    init();
+    let (mut sink, mut stream) = connect_echo("127.0.0.1:7922").await;
+
+    sink.send(request(1, "0123456789", true)).await.unwrap();
+    sink.send(fragment(1, "0123456789", true)).await.unwrap();
+    expect_invalid(&mut stream, 1).await;
+
+    // the rest fragments of the stream are treated as frames of a terminated stream.
+    sink.send(fragment(1, "0123456789", false)).await.unwrap();
+    sink.send(request(3, "hello", false)).await.unwrap();
+    expect_echo(&mut stream, 3, "hello").await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_interrupted_fragments() {
+    init();
+    let (mut sink, mut stream) = connect_echo("127.0.0.1:7923").await;
+
+    sink.send(request(1, "hello", true)).await.unwrap();
+    sink.send(frame::RequestStream::builder(1, 0).build())
+        .await
+        .unwrap();
+    expect_invalid(&mut stream, 1).await;
+
+    sink.send(request(3, "hello", false)).await.unwrap();
+    expect_echo(&mut stream, 3, "hello").await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_channel_responses_respect_mtu() {
+    init();
+    let addr = "127.0.0.1:7924";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .fragment(64)
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (mut sink, mut stream) = connect_raw(addr, None).await;
+
+    let data = "x".repeat(200);
+    sink.send(
+        frame::RequestChannel::builder(1, Frame::FLAG_COMPLETE)
+            .set_initial_request_n(8)
+            .set_data(Bytes::from(data.clone()))
+            .build(),
+    )
+    .await
+    .unwrap();
+
+    let mut fragments = 0;
+    let mut echoed = Vec::new();
+    loop {
+        let next = next_frame(&mut stream).await;
+        assert!(next.len() <= 64, "frame is {} bytes", next.len());
+        let complete = next.get_flag() & Frame::FLAG_COMPLETE != 0;
+        if let Body::Payload(p) = next.get_body() {
+            if let Some(d) = p.get_data() {
+                echoed.extend_from_slice(d);
+                fragments += 1;
+            }
+        }
+        if complete {
+            break;
+        }
+    }
+    assert!(fragments > 1);
+    assert_eq!(data.as_bytes(), &echoed[..]);
+}
+
+/// An echo responder which signals once it is dropped along with its connection.
+struct Released(mpsc::UnboundedSender<()>);
+
+impl Drop for Released {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+#[rsocket_rust::async_trait]
+impl RSocket for Released {
+    async fn metadata_push(&self, req: Payload) -> Result<()> {
+        EchoRSocket.metadata_push(req).await
+    }
+
+    async fn fire_and_forget(&self, req: Payload) -> Result<()> {
+        EchoRSocket.fire_and_forget(req).await
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        EchoRSocket.request_response(req).await
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        EchoRSocket.request_stream(req)
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        EchoRSocket.request_channel(reqs)
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_release_partial_fragments_on_disconnect() {
+    init();
+    let addr = "127.0.0.1:7925";
+    let (released_tx, mut released_rx) = mpsc::unbounded_channel();
+    let (mut sink, stream) = connect_raw(
+        addr,
+        Some(Box::new(move |_setup, _socket| {
+            Ok(Box::new(Released(released_tx.clone())))
+        })),
+    )
+    .await;
+    sink.send(request(1, "hello", true)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the connection, and the partial payload with it, is released once the client is gone.
+    drop(sink);
+    drop(stream);
+    tokio::time::timeout(Duration::from_secs(3), released_rx.recv())
+        .await
+        .expect("connection should be released");
+}
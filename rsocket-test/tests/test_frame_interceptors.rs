@@ -0,0 +1,134 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Direction, FrameInterceptor};
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::is_timeout;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// The direction, stream id and type of a frame.
+type Record = (Direction, u32, &'static str);
+
+/// Records every frame except KEEPALIVE.
+#[derive(Clone, Default)]
+struct Recorder {
+    frames: Arc<Mutex<Vec<Record>>>,
+}
+
+impl FrameInterceptor for Recorder {
+    fn intercept(&self, direction: Direction, frame: Frame) -> Option<Frame> {
+        let kind = match frame.get_body_ref() {
+            Body::Setup(_) => "SETUP",
+            Body::RequestResponse(_) => "REQUEST_RESPONSE",
+            Body::Payload(_) => "PAYLOAD",
+            Body::Keepalive(_) => return Some(frame),
+            _ => "OTHER",
+        };
+        self.frames
+            .lock()
+            .unwrap()
+            .push((direction, frame.get_stream_id(), kind));
+        Some(frame)
+    }
+}
+
+/// Drops requests whose data is "drop", and shouts the data of responses.
+struct FaultInjector;
+
+impl FrameInterceptor for FaultInjector {
+    fn intercept(&self, direction: Direction, frame: Frame) -> Option<Frame> {
+        match (direction, frame.get_body_ref()) {
+            (Direction::Inbound, Body::RequestResponse(req))
+                if req.get_data() == Some(&Bytes::from("drop")) =>
+            {
+                None
+            }
+            (Direction::Outbound, Body::Payload(res)) => {
+                let data = String::from_utf8_lossy(res.get_data().unwrap()).to_uppercase();
+                Some(
+                    frame::Payload::builder(frame.get_stream_id(), frame.get_flag())
+                        .set_data(Bytes::from(data))
+                        .build(),
+                )
+            }
+            _ => Some(frame),
+        }
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_observe_frames() {
+    init();
+    let addr = "127.0.0.1:7971";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let recorder = Recorder::default();
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .frame_interceptor(Box::new(recorder.clone()))
+        .start()
+        .await
+        .unwrap();
+    cli.request_response(Payload::from("hello")).await.unwrap();
+
+    let frames = recorder.frames.lock().unwrap().clone();
+    assert_eq!(
+        vec![
+            (Direction::Outbound, 0, "SETUP"),
+            (Direction::Outbound, 1, "REQUEST_RESPONSE"),
+            (Direction::Inbound, 1, "PAYLOAD"),
+        ],
+        frames
+    );
+}
+
+#[tokio::main]
+#[test]
+async fn test_modify_and_drop_frames() {
+    init();
+    let addr = "127.0.0.1:7972";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .frame_interceptor(Box::new(FaultInjector))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("HELLO"), res.unwrap().data_utf8());
+
+    let res = cli
+        .request_response_with_timeout(Payload::from("drop"), Duration::from_millis(200))
+        .await;
+    assert!(is_timeout(res), "the request should be dropped");
+}
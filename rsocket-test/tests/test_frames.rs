@@ -2,7 +2,11 @@ extern crate bytes;
 extern crate hex;
 extern crate rsocket_rust;
 
-use bytes::{Bytes, BytesMut};
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use proptest::prelude::*;
+use rsocket_rust::error::RSocketError;
 use rsocket_rust::frame::*;
 use rsocket_rust::utils::Writeable;
 
@@ -132,3 +136,210 @@ fn try_codec(f: Frame) {
         f, f2
     );
 }
+
+#[test]
+fn test_unknown_frame_type() {
+    let decode = |flag: u16| {
+        let mut bf = BytesMut::new();
+        bf.put_u32(1);
+        bf.put_u16((0x3F << 10) | flag);
+        bf.put_slice(b"foobar");
+        Frame::decode(&mut bf).unwrap_err()
+    };
+    assert!(matches!(
+        decode(0).downcast_ref::<RSocketError>(),
+        Some(RSocketError::UnknownFrameType(0x3F))
+    ));
+    assert!(matches!(
+        decode(Frame::FLAG_IGNORE).downcast_ref::<RSocketError>(),
+        Some(RSocketError::IgnoredFrame(0x3F))
+    ));
+}
+
+#[test]
+fn test_metadata_longer_than_frame() {
+    let mut bf = BytesMut::new();
+    bf.put_u32(1);
+    bf.put_u16((Frame::TYPE_PAYLOAD << 10) | Frame::FLAG_METADATA | Frame::FLAG_NEXT);
+    bf.put_slice(&[0x00, 0x00, 0xFF]);
+    bf.put_slice(b"foobar");
+    assert!(Frame::decode(&mut bf).is_err());
+}
+
+fn round_trip(f: &Frame) -> Frame {
+    let mut bf = BytesMut::with_capacity(f.len());
+    f.write_to(&mut bf);
+    Frame::decode(&mut bf).unwrap()
+}
+
+fn some_bytes() -> impl Strategy<Value = Bytes> {
+    proptest::collection::vec(any::<u8>(), 0..64).prop_map(Bytes::from)
+}
+
+/// Empty data can not be told from no data, so the data is either absent or non-empty.
+fn opt_data() -> impl Strategy<Value = Option<Bytes>> {
+    proptest::option::of(proptest::collection::vec(any::<u8>(), 1..64).prop_map(Bytes::from))
+}
+
+fn opt_metadata() -> impl Strategy<Value = Option<Bytes>> {
+    proptest::option::of(some_bytes())
+}
+
+fn request_flag() -> impl Strategy<Value = u16> {
+    any::<u16>().prop_map(|n| n & (Frame::FLAG_FOLLOW | Frame::FLAG_COMPLETE | Frame::FLAG_NEXT))
+}
+
+proptest! {
+    #[test]
+    fn prop_setup(
+        flag in prop_oneof![Just(0), Just(Frame::FLAG_LEASE)],
+        version in (any::<u16>(), any::<u16>()),
+        keepalive in any::<u32>(),
+        lifetime in any::<u32>(),
+        token in proptest::option::of(some_bytes()),
+        mime in ("[a-z/]{0,32}", "[a-z/]{0,32}"),
+        metadata in opt_metadata(),
+        data in opt_data(),
+    ) {
+        let mut bu = Setup::builder(0, flag)
+            .set_version(version.0, version.1)
+            .set_keepalive(Duration::from_millis(u64::from(keepalive)))
+            .set_lifetime(Duration::from_millis(u64::from(lifetime)))
+            .set_mime_metadata(mime.0)
+            .set_mime_data(mime.1);
+        if let Some(b) = token {
+            bu = bu.set_token(b);
+        }
+        if let Some(b) = metadata {
+            bu = bu.set_metadata(b);
+        }
+        if let Some(b) = data {
+            bu = bu.set_data(b);
+        }
+        let f = bu.build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_lease(
+        ttl in any::<u32>(),
+        n in any::<u32>(),
+        metadata in opt_metadata(),
+    ) {
+        let mut bu = Lease::builder(0, 0).set_ttl(ttl).set_number_of_requests(n);
+        if let Some(b) = metadata {
+            bu = bu.set_metadata(b);
+        }
+        let f = bu.build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_keepalive(
+        respond in any::<bool>(),
+        position in any::<u64>(),
+        data in opt_data(),
+    ) {
+        let flag = if respond { Frame::FLAG_RESPOND } else { 0 };
+        let mut bu = Keepalive::builder(0, flag).set_last_received_position(position);
+        if let Some(b) = data {
+            bu = bu.set_data(b);
+        }
+        let f = bu.build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_requests(
+        sid in any::<u32>(),
+        flag in request_flag(),
+        n in any::<u32>(),
+        metadata in opt_metadata(),
+        data in opt_data(),
+    ) {
+        let all = (data, metadata);
+        let frames = vec![
+            RequestFNF::builder(sid, flag).set_all(all.clone()).build(),
+            RequestResponse::builder(sid, flag).set_all(all.clone()).build(),
+            RequestStream::builder(sid, flag)
+                .set_initial_request_n(n)
+                .set_all(all.clone())
+                .build(),
+            RequestChannel::builder(sid, flag)
+                .set_initial_request_n(n)
+                .set_all(all.clone())
+                .build(),
+            Payload::builder(sid, flag).set_all(all).build(),
+        ];
+        for f in frames {
+            prop_assert_eq!(round_trip(&f), f);
+        }
+    }
+
+    #[test]
+    fn prop_stream_controls(sid in any::<u32>(), n in any::<u32>()) {
+        let frames = vec![
+            RequestN::builder(sid, 0).set_n(n).build(),
+            Cancel::builder(sid, 0).build(),
+        ];
+        for f in frames {
+            prop_assert_eq!(round_trip(&f), f);
+        }
+    }
+
+    #[test]
+    fn prop_error(sid in any::<u32>(), code in any::<u32>(), data in opt_data()) {
+        let mut bu = Error::builder(sid, 0).set_code(code);
+        if let Some(b) = data {
+            bu = bu.set_data(b);
+        }
+        let f = bu.build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_metadata_push(metadata in some_bytes()) {
+        let f = MetadataPush::builder(0, Frame::FLAG_METADATA)
+            .set_metadata(metadata)
+            .build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_resume(
+        token in opt_data(),
+        server_position in any::<u64>(),
+        client_position in any::<u64>(),
+        position in any::<u64>(),
+    ) {
+        let mut bu = Resume::builder(0, 0)
+            .set_last_received_server_position(server_position)
+            .set_first_available_client_position(client_position);
+        if let Some(b) = token {
+            bu = bu.set_token(b);
+        }
+        let f = bu.build();
+        prop_assert_eq!(round_trip(&f), f);
+
+        let f = ResumeOK::builder(0, 0).set_position(position).build();
+        prop_assert_eq!(round_trip(&f), f);
+    }
+
+    #[test]
+    fn prop_decode_arbitrary_bytes(raw in proptest::collection::vec(any::<u8>(), 0..256)) {
+        let _ = Frame::decode(&mut BytesMut::from(&raw[..]));
+    }
+
+    #[test]
+    fn prop_decode_arbitrary_body(
+        sid in any::<u32>(),
+        n in any::<u16>(),
+        body in proptest::collection::vec(any::<u8>(), 0..128),
+    ) {
+        let mut bf = BytesMut::new();
+        bf.put_u32(sid);
+        bf.put_u16(n);
+        bf.put_slice(&body);
+        let _ = Frame::decode(&mut bf);
+    }
+}
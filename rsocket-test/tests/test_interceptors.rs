@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::prelude::*;
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust::Result;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::mpsc;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Appends a tag to the data of every request.
+struct Tagging {
+    inner: Box<dyn RSocket>,
+    tag: &'static str,
+}
+
+impl Tagging {
+    fn interceptor(tag: &'static str) -> RSocketInterceptor {
+        Box::new(move |inner| Box::new(Tagging { inner, tag }))
+    }
+
+    fn tag(&self, req: Payload) -> Payload {
+        let data = format!("{}{}", req.data_utf8().unwrap_or_default(), self.tag);
+        Payload::builder().set_data_utf8(&data).build()
+    }
+}
+
+#[rsocket_rust::async_trait]
+impl RSocket for Tagging {
+    async fn metadata_push(&self, req: Payload) -> Result<()> {
+        self.inner.metadata_push(req).await
+    }
+
+    async fn fire_and_forget(&self, req: Payload) -> Result<()> {
+        self.inner.fire_and_forget(self.tag(req)).await
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        self.inner.request_response(self.tag(req)).await
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        self.inner.request_stream(self.tag(req))
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        self.inner.request_channel(reqs)
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_client_requester_and_server_responder() {
+    init();
+    let addr = "127.0.0.1:7961";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .responder_interceptor(Tagging::interceptor("c"))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .requester_interceptor(Tagging::interceptor("a"))
+        .requester_interceptor(Tagging::interceptor("b"))
+        .start()
+        .await
+        .unwrap();
+
+    // the last registered interceptor is the outermost.
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hellobac"), res.unwrap().data_utf8());
+    let mut results = cli.request_stream(Payload::from("hello"));
+    let res = results.next().await.unwrap().unwrap();
+    assert_eq!(Some("hellobac"), res.data_utf8());
+
+    // requests which override the limit rate or timeout are intercepted as well.
+    let timeout = Duration::from_secs(3);
+    let res = cli
+        .request_response_with_timeout(Payload::from("hello"), timeout)
+        .await
+        .unwrap();
+    assert_eq!(Some("hellobac"), res.unwrap().data_utf8());
+    let mut results = cli.request_stream_with_timeout(Payload::from("hello"), timeout);
+    let res = results.next().await.unwrap().unwrap();
+    assert_eq!(Some("hellobac"), res.data_utf8());
+    let mut results = cli.request_stream_with(Payload::from("hello"), LimitRate::new(2, 0));
+    let res = results.next().await.unwrap().unwrap();
+    assert_eq!(Some("hellobac"), res.data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_server_requester_and_client_responder() {
+    init();
+    let addr = "127.0.0.1:7962";
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(move |_setup, socket| {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let res = socket.request_response(Payload::from("ping")).await;
+                    tx.send(res.unwrap().unwrap()).unwrap();
+                });
+                Ok(Box::new(EchoRSocket))
+            }))
+            .requester_interceptor(Tagging::interceptor("s"))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let _cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .acceptor(Box::new(|| Box::new(EchoRSocket)))
+        .responder_interceptor(Tagging::interceptor("r"))
+        .start()
+        .await
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(3), rx.recv())
+        .await
+        .expect("should receive the response")
+        .unwrap();
+    assert_eq!(Some("pingsr"), res.data_utf8());
+}
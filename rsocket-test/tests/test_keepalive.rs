@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::frame::{self, Body};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Connection;
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+async fn serve_echo(addr: &'static str) {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_server_closes_silent_connection() {
+    init();
+    let addr = "127.0.0.1:7851";
+    serve_echo(addr).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    let setup = frame::Setup::builder(0, 0)
+        .set_keepalive(Duration::from_millis(100))
+        .set_lifetime(Duration::from_millis(300))
+        .build();
+    sink.send(setup).await.unwrap();
+
+    let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .expect("should be closed by server")
+        .unwrap()
+        .unwrap();
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_FAILED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .expect("should be closed by server");
+    assert!(next.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_client_detects_dead_peer() {
+    init();
+    let addr = "127.0.0.1:7852";
+    // a peer which accepts the connection but never responds.
+    let listener = TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        while let Ok(n) = socket.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+    let _cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .keepalive(Duration::from_millis(100), Duration::from_millis(100), 3)
+        .on_close_with(Box::new(move |reason| {
+            let expired = matches!(reason, Some(RSocketError::ConnectionException(_)));
+            closed_tx.send(expired).unwrap();
+        }))
+        .start()
+        .await
+        .unwrap();
+
+    let expired = tokio::time::timeout(Duration::from_secs(3), closed_rx.recv())
+        .await
+        .expect("should be closed after keepalive lifetime");
+    assert_eq!(Some(true), expired);
+}
+
+#[tokio::main]
+#[test]
+async fn test_keepalive_keeps_idle_connection() {
+    init();
+    let addr = "127.0.0.1:7853";
+    serve_echo(addr).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .keepalive(Duration::from_millis(100), Duration::from_millis(100), 3)
+        .start()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_pending_request_fails_after_expiry() {
+    init();
+    let addr = "127.0.0.1:7854";
+    // a peer which accepts the connection but never responds.
+    let listener = TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        while let Ok(n) = socket.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .keepalive(Duration::from_millis(100), Duration::from_millis(100), 3)
+        .start()
+        .await
+        .unwrap();
+
+    let res = tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await
+    .expect("should fail after keepalive lifetime");
+    match res {
+        Err(e) => assert!(matches!(
+            e.downcast_ref::<RSocketError>(),
+            Some(RSocketError::ConnectionException(_))
+        )),
+        Ok(_) => panic!("should fail"),
+    }
+
+    // requests sent afterwards fail at once.
+    let res = tokio::time::timeout(
+        Duration::from_millis(500),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await
+    .expect("should fail at once");
+    assert!(res.is_err());
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::stream;
+use rsocket_rust::transport::Connection;
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::{is_rejected, next_frame};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts an echo server which grants the lease once.
+async fn serve_lease(addr: &'static str, lease: Option<Lease>) {
+    tokio::spawn(async move {
+        let mut server = RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))));
+        if let Some(lease) = lease {
+            server = server.lease(Box::new(move || {
+                let lease = lease.clone();
+                Box::pin(stream! {
+                    yield lease;
+                    futures::future::pending::<()>().await;
+                })
+            }));
+        }
+        server.serve().await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_client_honors_lease() {
+    init();
+    let addr = "127.0.0.1:7831";
+    serve_lease(addr, Some(Lease::new(Duration::from_secs(10), 2))).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .lease()
+        .start()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    for _ in 0..2 {
+        let res = cli.request_response(Payload::from("hello")).await.unwrap();
+        assert_eq!(Some("hello"), res.unwrap().data_utf8());
+    }
+    let res = cli.request_response(Payload::from("hello")).await;
+    assert!(is_rejected(res));
+    let res = cli.fire_and_forget(Payload::from("hello")).await;
+    assert!(is_rejected(res));
+    let mut results = cli.request_stream(Payload::from("hello"));
+    assert!(is_rejected(results.next().await.unwrap()));
+    assert!(results.next().await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_client_rejects_expired_lease() {
+    init();
+    let addr = "127.0.0.1:7832";
+    serve_lease(addr, Some(Lease::new(Duration::from_millis(300), 10))).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .lease()
+        .start()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let res = cli.request_response(Payload::from("hello")).await;
+    assert!(is_rejected(res));
+}
+
+#[tokio::main]
+#[test]
+async fn test_lease_not_required() {
+    init();
+    let addr = "127.0.0.1:7833";
+    serve_lease(addr, Some(Lease::new(Duration::from_secs(10), 0))).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_server_rejects_unsupported_lease() {
+    init();
+    let addr = "127.0.0.1:7834";
+    serve_lease(addr, None).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(frame::Setup::builder(0, Frame::FLAG_LEASE).build())
+        .await
+        .unwrap();
+    let next = next_frame(&mut stream).await;
+    match next.get_body_ref() {
+        Body::Error(e) => assert_eq!(error::ERR_UNSUPPORTED_SETUP, e.get_code()),
+        _ => panic!("should receive ERROR: {:?}", next),
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_server_rejects_requests_beyond_lease() {
+    init();
+    let addr = "127.0.0.1:7835";
+    serve_lease(
+        addr,
+        Some(Lease::new(Duration::from_secs(10), 1).set_metadata("foo")),
+    )
+    .await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(frame::Setup::builder(0, Frame::FLAG_LEASE).build())
+        .await
+        .unwrap();
+    let next = next_frame(&mut stream).await;
+    match next.get_body_ref() {
+        Body::Lease(lease) => {
+            assert_eq!(10_000, lease.get_ttl());
+            assert_eq!(1, lease.get_number_of_requests());
+            assert_eq!(Some(&Bytes::from("foo")), lease.get_metadata());
+        }
+        _ => panic!("should receive LEASE: {:?}", next),
+    }
+
+    for sid in &[1, 3] {
+        let req = frame::RequestResponse::builder(*sid, 0)
+            .set_data(Bytes::from("hello"))
+            .build();
+        sink.send(req).await.unwrap();
+    }
+    let next = next_frame(&mut stream).await;
+    assert_eq!(1, next.get_stream_id());
+    assert!(matches!(next.get_body_ref(), Body::Payload(_)));
+    let next = next_frame(&mut stream).await;
+    assert_eq!(3, next.get_stream_id());
+    match next.get_body_ref() {
+        Body::Error(e) => assert_eq!(error::ERR_REJECTED, e.get_code()),
+        _ => panic!("should receive ERROR: {:?}", next),
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, ServerTransport, Transport};
+use rsocket_rust::utils::{u24, EchoRSocket, Writeable};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts an echo server, and connects to it with a raw TCP stream.
+async fn connect_raw(addr: &'static str) -> TcpStream {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(EchoRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut conn = TcpStream::connect(addr).await.unwrap();
+    let mut bf = BytesMut::new();
+    frame::Setup::builder(0, 0).build().write_to(&mut bf);
+    write_raw(&mut conn, &bf).await;
+    conn
+}
+
+async fn write_raw(conn: &mut TcpStream, raw: &[u8]) {
+    let mut bf = BytesMut::new();
+    u24::from(raw.len()).write_to(&mut bf);
+    bf.put_slice(raw);
+    conn.write_all(&bf).await.unwrap();
+}
+
+async fn read_frame(conn: &mut TcpStream) -> Option<Frame> {
+    let mut len = [0u8; 3];
+    let read = tokio::time::timeout(Duration::from_secs(3), conn.read_exact(&mut len))
+        .await
+        .expect("should receive next frame");
+    if read.is_err() {
+        return None;
+    }
+    let mut raw = vec![0u8; u24::parse(&len).into()];
+    conn.read_exact(&mut raw).await.unwrap();
+    Some(Frame::decode(&mut BytesMut::from(&raw[..])).unwrap())
+}
+
+/// A frame of the reserved extension type 0x3F.
+fn unknown_frame(flag: u16) -> BytesMut {
+    let mut bf = BytesMut::new();
+    bf.put_u32(0);
+    bf.put_u16((0x3F << 10) | flag);
+    bf.put_slice(b"unknown");
+    bf
+}
+
+#[tokio::main]
+#[test]
+async fn test_skip_ignorable_unknown_frame() {
+    init();
+    let mut conn = connect_raw("127.0.0.1:7931").await;
+
+    write_raw(&mut conn, &unknown_frame(Frame::FLAG_IGNORE)).await;
+    let mut bf = BytesMut::new();
+    frame::RequestResponse::builder(1, 0)
+        .set_data(Bytes::from("hello"))
+        .build()
+        .write_to(&mut bf);
+    write_raw(&mut conn, &bf).await;
+
+    let next = read_frame(&mut conn).await.unwrap();
+    assert_eq!(1, next.get_stream_id());
+    assert!(matches!(next.get_body_ref(), Body::Payload(_)));
+}
+
+#[tokio::main]
+#[test]
+async fn test_close_on_unknown_frame() {
+    init();
+    let mut conn = connect_raw("127.0.0.1:7932").await;
+
+    write_raw(&mut conn, &unknown_frame(0)).await;
+    let next = read_frame(&mut conn).await.unwrap();
+    assert_eq!(0, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_FAILED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    assert!(read_frame(&mut conn).await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_binary_error_data() {
+    init();
+    let addr = "127.0.0.1:7933";
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+
+    let connecting = tokio::spawn(async move {
+        RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .start()
+            .await
+            .unwrap()
+    });
+    let tp = server.next().await.unwrap().unwrap();
+    let (mut sink, mut stream) = tp.connect().await.unwrap().split();
+    let cli = connecting.await.unwrap();
+
+    let requesting =
+        tokio::spawn(async move { cli.request_response(Payload::from("hello")).await });
+    // SETUP, then the REQUEST_RESPONSE.
+    stream.next().await.unwrap().unwrap();
+    let req = stream.next().await.unwrap().unwrap();
+    sink.send(
+        frame::Error::builder(req.get_stream_id(), 0)
+            .set_code(error::ERR_APPLICATION)
+            .set_data(Bytes::from_static(&[0xC3, 0x28]))
+            .build(),
+    )
+    .await
+    .unwrap();
+
+    let e = requesting.await.unwrap().unwrap_err();
+    match e.downcast_ref::<RSocketError>() {
+        Some(RSocketError::BinaryError(code, data)) => {
+            assert_eq!(error::ERR_APPLICATION, *code);
+            assert_eq!(&[0xC3, 0x28][..], &data[..]);
+        }
+        _ => panic!("should be a binary error: {}", e),
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::error::RSocketError;
+use rsocket_rust::metrics::{InMemoryMetrics, Interaction, Side};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Direction;
+use rsocket_rust::{stream, Client, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+const ERR_RETRY_LATER: u32 = 0x0000_0301;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Echoes requests, but fails a request_response of "fail" and never completes a
+/// request_stream of "hang".
+struct Responder;
+
+#[rsocket_rust::async_trait]
+impl RSocket for Responder {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        match req.data_utf8() {
+            Some("fail") => Err(RSocketError::custom(ERR_RETRY_LATER, "later").into()),
+            _ => Ok(Some(req)),
+        }
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        match req.data_utf8() {
+            Some("hang") => Box::pin(futures::stream::pending()),
+            _ => Box::pin(stream! {
+                yield Ok(req);
+            }),
+        }
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        reqs
+    }
+}
+
+async fn connect(addr: &'static str) -> (Client, Arc<InMemoryMetrics>, Arc<InMemoryMetrics>) {
+    let server_metrics = Arc::new(InMemoryMetrics::new());
+    let recorder = server_metrics.clone();
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(Responder))))
+            .metrics(recorder)
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let client_metrics = Arc::new(InMemoryMetrics::new());
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .metrics(client_metrics.clone())
+        .start()
+        .await
+        .unwrap();
+    (cli, client_metrics, server_metrics)
+}
+
+/// Waits until the condition holds, metrics of the peer are recorded asynchronously.
+async fn eventually<F>(cond: F)
+where
+    F: Fn() -> bool,
+{
+    for _ in 0..300 {
+        if cond() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition does not hold");
+}
+
+#[tokio::main]
+#[test]
+async fn test_requests_and_bytes() {
+    init();
+    let (cli, client_metrics, server_metrics) = connect("127.0.0.1:7981").await;
+    eventually(|| server_metrics.open_connections() == 1).await;
+    assert_eq!(1, client_metrics.open_connections());
+
+    cli.fire_and_forget(Payload::from("hello")).await.unwrap();
+    cli.request_response(Payload::from("hello")).await.unwrap();
+    let results: Vec<_> = cli.request_stream(Payload::from("hello")).collect().await;
+    assert_eq!(1, results.len());
+    let reqs: Flux<Result<Payload>> = Box::pin(stream! {
+        yield Ok(Payload::from("hello"));
+    });
+    let results: Vec<_> = cli.request_channel(reqs).collect().await;
+    assert_eq!(1, results.len());
+
+    for interaction in [
+        Interaction::FireAndForget,
+        Interaction::RequestResponse,
+        Interaction::RequestStream,
+        Interaction::RequestChannel,
+    ] {
+        assert_eq!(1, client_metrics.requests(Side::Requester, interaction));
+        eventually(|| server_metrics.requests(Side::Responder, interaction) == 1).await;
+        eventually(|| client_metrics.active_streams(Side::Requester, interaction) == 0).await;
+        eventually(|| server_metrics.active_streams(Side::Responder, interaction) == 0).await;
+    }
+    assert_eq!(
+        0,
+        client_metrics.requests(Side::Responder, Interaction::RequestResponse)
+    );
+    assert_eq!(1, client_metrics.request_response_latency().count());
+    assert_eq!(0, server_metrics.request_response_latency().count());
+
+    // SETUP and the requests at least.
+    assert!(client_metrics.frames(Direction::Outbound) >= 5);
+    eventually(|| {
+        client_metrics.bytes(Direction::Outbound) == server_metrics.bytes(Direction::Inbound)
+    })
+    .await;
+    eventually(|| {
+        client_metrics.bytes(Direction::Inbound) == server_metrics.bytes(Direction::Outbound)
+    })
+    .await;
+
+    cli.close().await;
+    eventually(|| client_metrics.open_connections() == 0).await;
+    eventually(|| server_metrics.open_connections() == 0).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_active_streams_and_errors() {
+    init();
+    let (cli, client_metrics, server_metrics) = connect("127.0.0.1:7982").await;
+
+    let res = cli.request_response(Payload::from("fail")).await;
+    assert!(res.is_err());
+    assert_eq!(
+        1,
+        client_metrics.errors(Direction::Inbound, ERR_RETRY_LATER)
+    );
+    assert_eq!(
+        1,
+        server_metrics.errors(Direction::Outbound, ERR_RETRY_LATER)
+    );
+    assert_eq!(1, client_metrics.request_response_latency().count());
+
+    let results = cli.request_stream(Payload::from("hang"));
+    let stream = Interaction::RequestStream;
+    assert_eq!(1, client_metrics.active_streams(Side::Requester, stream));
+    eventually(|| server_metrics.active_streams(Side::Responder, stream) == 1).await;
+
+    // the stream is cancelled once it is dropped.
+    drop(results);
+    assert_eq!(0, client_metrics.active_streams(Side::Requester, stream));
+    eventually(|| server_metrics.active_streams(Side::Responder, stream) == 0).await;
+}
@@ -0,0 +1,232 @@
+#[macro_use]
+extern crate log;
+
+mod common;
+
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, FrameStream};
+use rsocket_rust::{stream, Result};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use common::connect_raw;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Streams `#0`, `#1` and so on, forever unless the request's data is the number of items.
+struct InfiniteRSocket;
+
+#[rsocket_rust::async_trait]
+impl RSocket for InfiniteRSocket {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        Ok(Some(req))
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        let count = req.data_utf8().and_then(|it| it.parse::<u64>().ok());
+        Box::pin(stream! {
+            let mut n = 0u64;
+            while count.is_none_or(|it| n < it) {
+                yield Ok(Payload::builder().set_data_utf8(&format!("#{}", n)).build());
+                n += 1;
+            }
+        })
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        reqs
+    }
+}
+
+fn serve_infinite(addr: &'static str) -> Runtime {
+    let server_runtime = Runtime::new().unwrap();
+    server_runtime.spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(InfiniteRSocket))))
+            .serve()
+            .await
+    });
+    sleep(Duration::from_millis(500));
+    server_runtime
+}
+
+async fn next_payloads(stream: &mut Box<FrameStream>, n: usize) -> Vec<Frame> {
+    let mut frames = vec![];
+    while frames.len() < n {
+        let next = stream.next().await.unwrap().unwrap();
+        if let Body::Payload(_) = next.get_body_ref() {
+            frames.push(next);
+        }
+    }
+    frames
+}
+
+async fn expect_silence(stream: &mut Box<FrameStream>) {
+    let res = tokio::time::timeout(Duration::from_millis(300), stream.next()).await;
+    assert!(res.is_err(), "should not receive more frames: {:?}", res);
+}
+
+/// Starts a raw server which sends `#0`, `#1` and so on as far as the requester has granted
+/// credits, and forwards all received frames.
+async fn serve_credited(addr: &'static str) -> mpsc::UnboundedReceiver<Frame> {
+    let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+    tokio::spawn(async move {
+        let tp = server.next().await.unwrap().unwrap();
+        let (mut sink, mut stream) = tp.connect().await.unwrap().split();
+        let mut sent = HashMap::new();
+        while let Some(Ok(next)) = stream.next().await {
+            let sid = next.get_stream_id();
+            let n = match next.get_body_ref() {
+                Body::RequestStream(it) => it.get_initial_request_n(),
+                Body::RequestN(it) => it.get_n(),
+                _ => 0,
+            };
+            // an unbounded demand is capped, the test only consumes a few payloads.
+            let seq = sent.entry(sid).or_insert(0u32);
+            for _ in 0..n.min(64) {
+                let sending = frame::Payload::builder(sid, Frame::FLAG_NEXT)
+                    .set_data(Bytes::from(format!("#{}", seq)))
+                    .build();
+                sink.send(sending).await.unwrap();
+                *seq += 1;
+            }
+            let _ = frames_tx.send(next);
+        }
+    });
+    frames_rx
+}
+
+/// Returns the credits granted to the stream by REQUEST_STREAM and REQUEST_N in order.
+async fn granted(frames: &mut mpsc::UnboundedReceiver<Frame>, sid: u32) -> Vec<u32> {
+    let mut credits = vec![];
+    while let Ok(Some(next)) = tokio::time::timeout(Duration::from_millis(300), frames.recv()).await
+    {
+        if next.get_stream_id() != sid {
+            continue;
+        }
+        match next.get_body_ref() {
+            Body::RequestStream(it) => credits.push(it.get_initial_request_n()),
+            Body::RequestN(it) => credits.push(it.get_n()),
+            _ => (),
+        }
+    }
+    credits
+}
+
+#[test]
+fn test_responder_honors_request_n() {
+    init();
+    let addr = "127.0.0.1:7801";
+    let _server = serve_infinite(addr);
+
+    Runtime::new().unwrap().block_on(async move {
+        let (mut sink, mut stream) = connect_raw(addr, None).await;
+
+        let req = frame::RequestStream::builder(1, 0)
+            .set_initial_request_n(2)
+            .set_data(Bytes::from("hello"))
+            .build();
+        sink.send(req).await.unwrap();
+
+        let frames = next_payloads(&mut stream, 2).await;
+        assert!(frames.iter().all(|it| it.has_next()));
+        expect_silence(&mut stream).await;
+
+        sink.send(frame::RequestN::builder(1, 0).set_n(3).build())
+            .await
+            .unwrap();
+        let frames = next_payloads(&mut stream, 3).await;
+        info!("got payloads after REQUEST_N: {:?}", frames);
+        expect_silence(&mut stream).await;
+    });
+}
+
+#[test]
+fn test_requester_replenishes_request_n() {
+    init();
+    let addr = "127.0.0.1:7802";
+
+    Runtime::new().unwrap().block_on(async move {
+        let mut frames = serve_credited(addr).await;
+        let cli = RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .limit_rate(4, 1)
+            .start()
+            .await
+            .unwrap();
+
+        let mut results = cli.request_stream(Payload::from("hello"));
+        for i in 0..20 {
+            let next = tokio::time::timeout(Duration::from_secs(3), results.next())
+                .await
+                .expect("should replenish REQUEST_N")
+                .unwrap()
+                .unwrap();
+            assert_eq!(Some(format!("#{}", i).as_str()), next.data_utf8());
+        }
+        // REQUEST_N tops the demand up to the high tide once it falls to the low tide.
+        assert_eq!(vec![4, 3, 3, 3, 3, 3, 3], granted(&mut frames, 1).await);
+
+        let mut results = cli.request_stream_with(Payload::from("hello"), LimitRate::new(2, 0));
+        for _ in 0..10 {
+            let next = tokio::time::timeout(Duration::from_secs(3), results.next()).await;
+            assert!(next.expect("should replenish REQUEST_N").unwrap().is_ok());
+        }
+        assert_eq!(vec![2, 2, 2, 2, 2], granted(&mut frames, 3).await);
+    });
+}
+
+#[test]
+fn test_responder_completes_without_credits() {
+    init();
+    let addr = "127.0.0.1:7803";
+    let _server = serve_infinite(addr);
+
+    Runtime::new().unwrap().block_on(async move {
+        let (mut sink, mut stream) = connect_raw(addr, None).await;
+
+        // the credits are exhausted by the last items, COMPLETE needs none.
+        let req = frame::RequestStream::builder(1, 0)
+            .set_initial_request_n(2)
+            .set_data(Bytes::from("2"))
+            .build();
+        sink.send(req).await.unwrap();
+        let frames = next_payloads(&mut stream, 3).await;
+        assert!(frames[..2].iter().all(|it| it.has_next()));
+        assert!(frames[2].has_complete());
+
+        let req = frame::RequestChannel::builder(3, Frame::FLAG_COMPLETE)
+            .set_initial_request_n(1)
+            .set_data(Bytes::from("hello"))
+            .build();
+        sink.send(req).await.unwrap();
+        let frames = next_payloads(&mut stream, 2).await;
+        assert!(frames[0].has_next());
+        assert!(frames[1].has_complete());
+        expect_silence(&mut stream).await;
+    });
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Connection;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use common::TickRSocket;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts a TCP proxy whose connections will be dropped once the returned Notify is notified.
+async fn start_proxy(addr: &'static str, upstream: &'static str) -> Arc<Notify> {
+    let kill = Arc::new(Notify::new());
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let cloned_kill = kill.clone();
+    tokio::spawn(async move {
+        while let Ok((mut inbound, _)) = listener.accept().await {
+            let kill = cloned_kill.clone();
+            tokio::spawn(async move {
+                let mut outbound = TcpStream::connect(upstream).await.unwrap();
+                tokio::select! {
+                    _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => (),
+                    _ = kill.notified() => (),
+                }
+            });
+        }
+    });
+    kill
+}
+
+async fn serve_resume(addr: &'static str) {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .resume()
+            .acceptor(Box::new(|_setup, _socket| {
+                Ok(Box::new(TickRSocket(Some(20))))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+async fn next_error_code(addr: &'static str, first: Frame) -> u32 {
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(first).await.unwrap();
+    let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    match next.get_body() {
+        Body::Error(e) => e.get_code(),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_resume_in_flight_streams() {
+    init();
+    let addr = "127.0.0.1:7841";
+    let proxy_addr = "127.0.0.1:7842";
+    serve_resume(addr).await;
+    let kill = start_proxy(proxy_addr, addr).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(proxy_addr))
+        .resume("foobar", move || TcpClientTransport::from(proxy_addr))
+        .start()
+        .await
+        .unwrap();
+
+    let mut results = cli.request_stream(Payload::from("hello"));
+    for i in 0..5 {
+        let next = results.next().await.unwrap().unwrap();
+        assert_eq!(Some(format!("#{}", i).as_str()), next.data_utf8());
+    }
+
+    kill.notify_waiters();
+    // the response is late enough to outlive the dropped connection.
+    let res = cli.request_response(Payload::from("late"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    kill.notify_waiters();
+
+    for i in 5..20 {
+        let next = tokio::time::timeout(Duration::from_secs(5), results.next())
+            .await
+            .expect("stream should survive the dropped connection")
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(format!("#{}", i).as_str()), next.data_utf8());
+    }
+    assert!(results.next().await.is_none());
+
+    let res = tokio::time::timeout(Duration::from_secs(5), res)
+        .await
+        .expect("request should survive the dropped connection")
+        .unwrap();
+    assert_eq!(Some("late"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_unknown_session() {
+    init();
+    let addr = "127.0.0.1:7843";
+    serve_resume(addr).await;
+
+    let resume = frame::Resume::builder(0, 0)
+        .set_token(Bytes::from("unknown"))
+        .build();
+    assert_eq!(
+        error::ERR_REJECT_RESUME,
+        next_error_code(addr, resume).await
+    );
+}
+
+#[tokio::main]
+#[test]
+async fn test_resume_unsupported() {
+    init();
+    let addr = "127.0.0.1:7844";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| {
+                Ok(Box::new(TickRSocket(Some(20))))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_token(Bytes::from("foobar"))
+        .build();
+    assert_eq!(
+        error::ERR_UNSUPPORTED_SETUP,
+        next_error_code(addr, setup).await
+    );
+}
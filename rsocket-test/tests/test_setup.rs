@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error::{self, RSocketError};
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Connection;
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::sync::mpsc;
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Starts a server which only accepts the `application/json` data MIME type.
+async fn serve_json(addr: &'static str) {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|setup, _socket| match setup.data_mime_type() {
+                Some("application/json") => Ok(Box::new(EchoRSocket)),
+                Some("text/plain") => {
+                    Err(RSocketError::UnsupportedSetup("unsupported data mime type".into()).into())
+                }
+                _ => Err(RSocketError::WithDescription("go away".into()).into()),
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+/// Sends the frames, and returns the code of the connection ERROR which closes the connection.
+async fn connection_error_code(addr: &'static str, frames: Vec<Frame>) -> u32 {
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    for next in frames {
+        sink.send(next).await.unwrap();
+    }
+    let mut code = None;
+    while let Some(next) = tokio::time::timeout(Duration::from_secs(3), stream.next())
+        .await
+        .expect("should be closed")
+    {
+        let next = next.unwrap();
+        if let Body::Error(e) = next.get_body_ref() {
+            assert_eq!(0, next.get_stream_id());
+            code = Some(e.get_code());
+        }
+    }
+    code.expect("should receive ERROR")
+}
+
+async fn setup_error_code(addr: &'static str, setup: Frame) -> u32 {
+    connection_error_code(addr, vec![setup]).await
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_invalid_setup() {
+    init();
+    let addr = "127.0.0.1:7881";
+    serve_json(addr).await;
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_version(2, 0)
+        .set_mime_data("application/json")
+        .build();
+    assert_eq!(
+        error::ERR_UNSUPPORTED_SETUP,
+        setup_error_code(addr, setup).await
+    );
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_keepalive(Duration::from_secs(0))
+        .set_mime_data("application/json")
+        .build();
+    assert_eq!(
+        error::ERR_INVALID_SETUP,
+        setup_error_code(addr, setup).await
+    );
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_lifetime(Duration::from_secs(0))
+        .set_mime_data("application/json")
+        .build();
+    assert_eq!(
+        error::ERR_INVALID_SETUP,
+        setup_error_code(addr, setup).await
+    );
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_mime_data("application/jsön")
+        .build();
+    assert_eq!(
+        error::ERR_INVALID_SETUP,
+        setup_error_code(addr, setup).await
+    );
+}
+
+#[tokio::main]
+#[test]
+async fn test_acceptor_chooses_error_code() {
+    init();
+    let addr = "127.0.0.1:7882";
+    serve_json(addr).await;
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_mime_data("text/plain")
+        .build();
+    assert_eq!(
+        error::ERR_UNSUPPORTED_SETUP,
+        setup_error_code(addr, setup).await
+    );
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_mime_data("application/xml")
+        .build();
+    assert_eq!(error::ERR_REJECT_SETUP, setup_error_code(addr, setup).await);
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .data_mime_type("application/json")
+        .start()
+        .await
+        .unwrap();
+    let res = cli.request_response(Payload::from("hello")).await.unwrap();
+    assert_eq!(Some("hello"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_acceptor_sees_version_and_flags() {
+    init();
+    let addr = "127.0.0.1:7883";
+    let (setup_tx, mut setup_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(move |setup, _socket| {
+                let version = setup.version();
+                setup_tx
+                    .send((version.get_major(), version.get_minor(), setup.flags()))
+                    .unwrap();
+                Ok(Box::new(EchoRSocket))
+            }))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, _stream) = conn.split();
+    let setup = frame::Setup::builder(0, Frame::FLAG_METADATA)
+        .set_version(1, 2)
+        .set_metadata(bytes::Bytes::from("foo"))
+        .build();
+    sink.send(setup).await.unwrap();
+
+    let received = tokio::time::timeout(Duration::from_secs(3), setup_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((1, 2, Frame::FLAG_METADATA), received);
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_requests_before_setup() {
+    init();
+    let addr = "127.0.0.1:7884";
+    serve_json(addr).await;
+
+    let req = frame::RequestResponse::builder(1, 0)
+        .set_data(bytes::Bytes::from("hello"))
+        .build();
+    assert_eq!(
+        error::ERR_INVALID_SETUP,
+        connection_error_code(addr, vec![req]).await
+    );
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_duplicate_setup() {
+    init();
+    let addr = "127.0.0.1:7885";
+    serve_json(addr).await;
+
+    let setup = frame::Setup::builder(0, 0)
+        .set_mime_data("application/json")
+        .build();
+    assert_eq!(
+        error::ERR_CONN_FAILED,
+        connection_error_code(addr, vec![setup.clone(), setup]).await
+    );
+}
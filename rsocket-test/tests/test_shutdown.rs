@@ -0,0 +1,185 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use rsocket_rust::error;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::Connection;
+use rsocket_rust::{stream, Result, ShutdownHandle};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tokio::task::JoinHandle;
+
+use common::{is_rejected, next_frame, next_or_closed, TickRSocket};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+async fn serve_tick(
+    addr: &'static str,
+    n: Option<usize>,
+    lease: bool,
+    handle: ShutdownHandle,
+) -> JoinHandle<Result<()>> {
+    let serving = tokio::spawn(async move {
+        let mut server = RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .shutdown_handle(handle)
+            .acceptor(Box::new(move |_setup, _socket| {
+                Ok(Box::new(TickRSocket(n)))
+            }));
+        if lease {
+            server = server.lease(Box::new(|| {
+                Box::pin(stream! {
+                    yield Lease::new(Duration::from_secs(10), 10);
+                    futures::future::pending::<()>().await;
+                })
+            }));
+        }
+        server.serve().await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    serving
+}
+
+#[tokio::main]
+#[test]
+async fn test_shutdown_drains_in_flight_requests() {
+    init();
+    let addr = "127.0.0.1:7871";
+    let handle = ShutdownHandle::new();
+    let serving = serve_tick(addr, Some(5), false, handle.clone()).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+    let mut results = cli.request_stream(Payload::from("hello"));
+    let consumer = tokio::spawn(async move {
+        let mut received = vec![];
+        while let Some(Ok(next)) = results.next().await {
+            received.push(next.data_utf8().unwrap().to_owned());
+        }
+        received
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let shutdown = tokio::spawn(async move { handle.shutdown(Duration::from_secs(2)).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // new requests are rejected while draining.
+    assert!(is_rejected(
+        cli.request_response(Payload::from("hello")).await
+    ));
+
+    tokio::time::timeout(Duration::from_secs(3), shutdown)
+        .await
+        .expect("should be shut down")
+        .unwrap();
+    assert!(serving.await.unwrap().is_ok());
+    let received = consumer.await.unwrap();
+    assert_eq!(vec!["#0", "#1", "#2", "#3", "#4"], received);
+}
+
+#[tokio::main]
+#[test]
+async fn test_shutdown_cancels_at_deadline() {
+    init();
+    let addr = "127.0.0.1:7872";
+    let handle = ShutdownHandle::new();
+    let serving = serve_tick(addr, None, false, handle.clone()).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(frame::Setup::builder(0, 0).build())
+        .await
+        .unwrap();
+    sink.send(frame::RequestStream::builder(1, 0).build())
+        .await
+        .unwrap();
+    assert_eq!(1, next_frame(&mut stream).await.get_stream_id());
+
+    tokio::time::timeout(
+        Duration::from_secs(3),
+        handle.shutdown(Duration::from_millis(200)),
+    )
+    .await
+    .expect("should be shut down");
+    assert!(serving.await.unwrap().is_ok());
+
+    // the stream is cancelled, and the connection is closed.
+    let mut last = None;
+    while let Some(next) = next_or_closed(&mut stream).await {
+        last = Some(next);
+    }
+    let last = last.unwrap();
+    assert_eq!(0, last.get_stream_id());
+    match last.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_CLOSED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_shutdown_revokes_lease() {
+    init();
+    let addr = "127.0.0.1:7873";
+    let handle = ShutdownHandle::new();
+    let serving = serve_tick(addr, Some(5), true, handle.clone()).await;
+
+    let conn = TcpClientTransport::from(addr).connect().await.unwrap();
+    let (mut sink, mut stream) = conn.split();
+    sink.send(frame::Setup::builder(0, Frame::FLAG_LEASE).build())
+        .await
+        .unwrap();
+    match next_frame(&mut stream).await.get_body() {
+        Body::Lease(lease) => assert_eq!(10, lease.get_number_of_requests()),
+        body => panic!("should receive LEASE: {:?}", body),
+    }
+
+    let shutdown = tokio::spawn(async move { handle.shutdown(Duration::from_secs(1)).await });
+    match next_frame(&mut stream).await.get_body() {
+        Body::Lease(lease) => assert_eq!(0, lease.get_number_of_requests()),
+        body => panic!("should receive LEASE: {:?}", body),
+    }
+    match next_frame(&mut stream).await.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_CLOSED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    assert!(next_or_closed(&mut stream).await.is_none());
+
+    shutdown.await.unwrap();
+    assert!(serving.await.unwrap().is_ok());
+}
+
+#[tokio::main]
+#[test]
+async fn test_shutdown_without_serving() {
+    init();
+    let addr = "127.0.0.1:7874";
+    let shutdown = |handle: ShutdownHandle| async move {
+        tokio::time::timeout(
+            Duration::from_secs(3),
+            handle.shutdown(Duration::from_millis(100)),
+        )
+        .await
+        .expect("should be shut down")
+    };
+
+    // the handle is not used by any server.
+    shutdown(ShutdownHandle::new()).await;
+
+    // the server fails to start since the address is in use.
+    let _listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    let handle = ShutdownHandle::new();
+    let serving = serve_tick(addr, None, false, handle.clone()).await;
+    assert!(serving.await.unwrap().is_err());
+    shutdown(handle).await;
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use bytes::Bytes;
+use futures::SinkExt;
+use rsocket_rust::error;
+use rsocket_rust::frame::{self, Body, Frame};
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, FrameSink, FrameStream, ServerTransport, Transport};
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::{connect_raw, expect_invalid, next_frame, next_or_closed, SlowRSocket};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+/// Checks the connection is still usable after an illegal frame.
+async fn expect_usable(sink: &mut Box<FrameSink>, stream: &mut Box<FrameStream>, sid: u32) {
+    sink.send(
+        frame::RequestResponse::builder(sid, 0)
+            .set_data(Bytes::from("hello"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    let next = next_frame(stream).await;
+    assert_eq!(sid, next.get_stream_id());
+    assert!(matches!(next.get_body_ref(), Body::Payload(_)));
+}
+
+#[tokio::main]
+#[test]
+async fn test_payload_on_responder_stream() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7891",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    sink.send(
+        frame::RequestResponse::builder(1, 0)
+            .set_data(Bytes::from("slow"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    sink.send(
+        frame::Payload::builder(1, Frame::FLAG_NEXT | Frame::FLAG_COMPLETE)
+            .set_data(Bytes::from("illegal"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    expect_invalid(&mut stream, 1).await;
+
+    sink.send(frame::RequestStream::builder(3, 0).build())
+        .await
+        .unwrap();
+    sink.send(frame::Payload::builder(3, Frame::FLAG_NEXT).build())
+        .await
+        .unwrap();
+    expect_invalid(&mut stream, 3).await;
+
+    expect_usable(&mut sink, &mut stream, 5).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_reuse_in_flight_stream_id() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7892",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    let req = frame::RequestResponse::builder(1, 0)
+        .set_data(Bytes::from("slow"))
+        .build();
+    sink.send(req.clone()).await.unwrap();
+    sink.send(req).await.unwrap();
+    expect_invalid(&mut stream, 1).await;
+
+    expect_usable(&mut sink, &mut stream, 3).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_payload_after_complete_on_channel() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7893",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    sink.send(
+        frame::RequestChannel::builder(1, 0)
+            .set_initial_request_n(8)
+            .set_data(Bytes::from("first"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    sink.send(frame::RequestN::builder(1, 0).set_n(8).build())
+        .await
+        .unwrap();
+    sink.send(frame::Payload::builder(1, Frame::FLAG_COMPLETE).build())
+        .await
+        .unwrap();
+    // the echoed payload, and COMPLETE of the responder.
+    loop {
+        let next = next_frame(&mut stream).await;
+        assert_eq!(1, next.get_stream_id());
+        if next.has_complete() {
+            break;
+        }
+    }
+
+    // the channel is over, frames for a terminated stream are ignored.
+    sink.send(frame::Payload::builder(1, Frame::FLAG_NEXT).build())
+        .await
+        .unwrap();
+    expect_usable(&mut sink, &mut stream, 3).await;
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_wrong_parity_stream_id() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7894",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    // requests from a client must use odd stream ids.
+    sink.send(
+        frame::RequestResponse::builder(2, 0)
+            .set_data(Bytes::from("hello"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    let next = next_frame(&mut stream).await;
+    assert_eq!(0, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_FAILED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    assert!(next_or_closed(&mut stream).await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_metadata_push_on_stream_zero() {
+    init();
+    let addr = "127.0.0.1:7895";
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+
+    let connecting = tokio::spawn(async move {
+        RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .start()
+            .await
+            .unwrap()
+    });
+    let tp = server.next().await.unwrap().unwrap();
+    let (_sink, mut stream) = tp.connect().await.unwrap().split();
+    let cli = connecting.await.unwrap();
+    let setup = next_frame(&mut stream).await;
+    assert!(matches!(setup.get_body_ref(), Body::Setup(_)));
+
+    for _ in 0..2 {
+        cli.metadata_push(Payload::builder().set_metadata_utf8("hello").build())
+            .await
+            .unwrap();
+        let next = next_frame(&mut stream).await;
+        assert_eq!(0, next.get_stream_id());
+        assert!(matches!(next.get_body_ref(), Body::MetadataPush(_)));
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_metadata_push_on_stream() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7896",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    sink.send(
+        frame::MetadataPush::builder(1, 0)
+            .set_metadata(Bytes::from("hello"))
+            .build(),
+    )
+    .await
+    .unwrap();
+    let next = next_frame(&mut stream).await;
+    assert_eq!(0, next.get_stream_id());
+    match next.get_body() {
+        Body::Error(e) => assert_eq!(error::ERR_CONN_FAILED, e.get_code()),
+        body => panic!("should receive ERROR: {:?}", body),
+    }
+    assert!(next_or_closed(&mut stream).await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_reject_zero_request_n() {
+    init();
+    let (mut sink, mut stream) = connect_raw(
+        "127.0.0.1:7897",
+        Some(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket)))),
+    )
+    .await;
+
+    sink.send(
+        frame::RequestStream::builder(1, 0)
+            .set_initial_request_n(0)
+            .build(),
+    )
+    .await
+    .unwrap();
+    expect_invalid(&mut stream, 1).await;
+
+    sink.send(
+        frame::RequestChannel::builder(3, 0)
+            .set_initial_request_n(0)
+            .build(),
+    )
+    .await
+    .unwrap();
+    expect_invalid(&mut stream, 3).await;
+
+    sink.send(frame::RequestStream::builder(5, 0).build())
+        .await
+        .unwrap();
+    sink.send(frame::RequestN::builder(5, 0).set_n(0).build())
+        .await
+        .unwrap();
+    expect_invalid(&mut stream, 5).await;
+
+    expect_usable(&mut sink, &mut stream, 7).await;
+}
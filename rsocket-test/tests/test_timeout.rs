@@ -0,0 +1,131 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsocket_rust::frame::Body;
+use rsocket_rust::prelude::*;
+use rsocket_rust::transport::{Connection, ServerTransport, Transport};
+use rsocket_rust::Client;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+
+use common::{is_timeout, SlowRSocket};
+
+fn init() {
+    let _ = env_logger::builder()
+        .format_timestamp_millis()
+        .is_test(true)
+        .try_init();
+}
+
+async fn connect_slow(addr: &'static str, timeout: Duration) -> Client {
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(SlowRSocket))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .request_timeout(timeout)
+        .start()
+        .await
+        .unwrap()
+}
+
+#[tokio::main]
+#[test]
+async fn test_request_timeout_sends_cancel() {
+    init();
+    let addr = "127.0.0.1:7911";
+    let mut server = TcpServerTransport::from(addr);
+    server.start().await.unwrap();
+
+    let connecting = tokio::spawn(async move {
+        RSocketFactory::connect()
+            .transport(TcpClientTransport::from(addr))
+            .request_timeout(Duration::from_millis(200))
+            .start()
+            .await
+            .unwrap()
+    });
+    let tp = server.next().await.unwrap().unwrap();
+    let (_sink, mut stream) = tp.connect().await.unwrap().split();
+    let cli = connecting.await.unwrap();
+
+    let res = tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.request_response(Payload::from("hello")),
+    )
+    .await
+    .expect("should time out");
+    assert!(is_timeout(res));
+
+    let mut received = vec![];
+    while received.len() < 3 {
+        let next = tokio::time::timeout(Duration::from_secs(3), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        received.push(next);
+    }
+    assert!(matches!(received[0].get_body_ref(), Body::Setup(_)));
+    assert!(matches!(
+        received[1].get_body_ref(),
+        Body::RequestResponse(_)
+    ));
+    assert!(matches!(received[2].get_body_ref(), Body::Cancel()));
+    assert_eq!(received[1].get_stream_id(), received[2].get_stream_id());
+}
+
+#[tokio::main]
+#[test]
+async fn test_override_default_timeout() {
+    init();
+    let cli = connect_slow("127.0.0.1:7912", Duration::from_millis(100)).await;
+
+    assert!(is_timeout(
+        cli.request_response(Payload::from("late")).await
+    ));
+    let res = cli
+        .request_response_with_timeout(Payload::from("late"), Duration::from_secs(3))
+        .await
+        .unwrap();
+    assert_eq!(Some("late"), res.unwrap().data_utf8());
+}
+
+#[tokio::main]
+#[test]
+async fn test_stream_timeout() {
+    init();
+    let cli = connect_slow("127.0.0.1:7913", Duration::from_secs(30)).await;
+
+    let mut results =
+        cli.request_stream_with_timeout(Payload::from("hello"), Duration::from_millis(200));
+    let next = tokio::time::timeout(Duration::from_secs(3), results.next())
+        .await
+        .expect("should time out")
+        .unwrap();
+    assert!(is_timeout(next));
+    assert!(results.next().await.is_none());
+}
+
+#[tokio::main]
+#[test]
+async fn test_default_timeout_spares_streams() {
+    init();
+    let cli = connect_slow("127.0.0.1:7914", Duration::from_millis(200)).await;
+
+    // the stream outlives the default timeout of request_response.
+    let results: Vec<_> = tokio::time::timeout(
+        Duration::from_secs(3),
+        cli.request_stream(Payload::from("6")).collect::<Vec<_>>(),
+    )
+    .await
+    .expect("should complete");
+    assert_eq!(6, results.len());
+    assert!(results.iter().all(|it| it.is_ok()));
+}
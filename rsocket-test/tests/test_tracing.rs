@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rsocket_rust::error::RSocketError;
+use rsocket_rust::prelude::*;
+use rsocket_rust::utils::EchoRSocket;
+use rsocket_rust::Result;
+use rsocket_rust_transport_tcp::{TcpClientTransport, TcpServerTransport};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// The fields of a span or an event.
+type Fields = HashMap<&'static str, String>;
+
+struct Span {
+    name: &'static str,
+    parent: Option<u64>,
+    fields: Fields,
+}
+
+/// Collects all the spans and events.
+#[derive(Default)]
+struct Collector {
+    seq: AtomicU64,
+    spans: Mutex<HashMap<u64, Span>>,
+    events: Mutex<Vec<(Option<u64>, Fields)>>,
+}
+
+struct Visitor<'a>(&'a mut Fields);
+
+impl Visit for Visitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut fields = Fields::new();
+        attrs.record(&mut Visitor(&mut fields));
+        let span = Span {
+            name: attrs.metadata().name(),
+            parent: attrs.parent().map(Id::into_u64),
+            fields,
+        };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::new();
+        event.record(&mut Visitor(&mut fields));
+        let parent = event.parent().map(Id::into_u64);
+        self.events.lock().unwrap().push((parent, fields));
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+impl Collector {
+    /// Returns the id of the span with the name and fields.
+    fn find(&self, name: &str, fields: &[(&str, &str)]) -> Option<u64> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, span)| {
+                span.name == name
+                    && fields
+                        .iter()
+                        .all(|(k, v)| span.fields.get(k).map(String::as_str) == Some(*v))
+            })
+            .map(|(id, _)| *id)
+    }
+
+    fn parent_of(&self, id: u64) -> Option<u64> {
+        self.spans.lock().unwrap().get(&id).and_then(|it| it.parent)
+    }
+
+    fn has_error(&self, parent: u64) -> bool {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(it, fields)| *it == Some(parent) && fields.contains_key("error"))
+    }
+}
+
+/// Echoes requests, but fails a request_response of "fail".
+struct Responder;
+
+#[rsocket_rust::async_trait]
+impl RSocket for Responder {
+    async fn metadata_push(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, _req: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        match req.data_utf8() {
+            Some("fail") => Err(RSocketError::ApplicationException("broken".into()).into()),
+            _ => EchoRSocket.request_response(req).await,
+        }
+    }
+
+    fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
+        EchoRSocket.request_stream(req)
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        EchoRSocket.request_channel(reqs)
+    }
+}
+
+#[tokio::main]
+#[test]
+async fn test_connection_and_stream_spans() {
+    let collector = Arc::new(Collector::default());
+    tracing::subscriber::set_global_default(collector.clone()).unwrap();
+
+    let addr = "127.0.0.1:7991";
+    tokio::spawn(async move {
+        RSocketFactory::receive()
+            .transport(TcpServerTransport::from(addr))
+            .acceptor(Box::new(|_setup, _socket| Ok(Box::new(Responder))))
+            .serve()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let cli = RSocketFactory::connect()
+        .transport(TcpClientTransport::from(addr))
+        .start()
+        .await
+        .unwrap();
+    cli.request_response(Payload::from("hello")).await.unwrap();
+    assert!(cli.request_response(Payload::from("fail")).await.is_err());
+
+    let client = collector
+        .find("rsocket.connection", &[("side", "client"), ("peer", addr)])
+        .expect("client connection span");
+    let server = collector
+        .find("rsocket.connection", &[("side", "server")])
+        .expect("server connection span");
+
+    let requester = collector
+        .find(
+            "rsocket.stream",
+            &[
+                ("stream_id", "1"),
+                ("side", "Requester"),
+                ("interaction", "RequestResponse"),
+            ],
+        )
+        .expect("requester stream span");
+    assert_eq!(Some(client), collector.parent_of(requester));
+    let responder = collector
+        .find(
+            "rsocket.stream",
+            &[("stream_id", "1"), ("side", "Responder")],
+        )
+        .expect("responder stream span");
+    assert_eq!(Some(server), collector.parent_of(responder));
+
+    // the failure is recorded on both sides of the stream.
+    let failed = [("stream_id", "3"), ("side", "Requester")];
+    assert!(collector.has_error(collector.find("rsocket.stream", &failed).unwrap()));
+    let failed = [("stream_id", "3"), ("side", "Responder")];
+    assert!(collector.has_error(collector.find("rsocket.stream", &failed).unwrap()));
+}
@@ -30,6 +30,13 @@ impl Transport for TcpClientTransport {
             },
         }
     }
+
+    fn peer(&self) -> Option<String> {
+        match &self.connector {
+            Connector::Direct(socket) => socket.peer_addr().ok().map(|it| it.to_string()),
+            Connector::Lazy(addr) => Some(addr.to_string()),
+        }
+    }
 }
 
 impl From<TcpStream> for TcpClientTransport {
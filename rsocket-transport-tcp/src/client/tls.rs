@@ -41,6 +41,19 @@ impl Transport for TlsClientTransport {
             },
         }
     }
+
+    fn peer(&self) -> Option<String> {
+        match &self.connector {
+            Connector::Direct(stream) => stream
+                .get_ref()
+                .get_ref()
+                .get_ref()
+                .peer_addr()
+                .ok()
+                .map(|it| it.to_string()),
+            Connector::Lazy(_, addr, _) => Some(addr.to_string()),
+        }
+    }
 }
 
 impl From<TlsStream<TcpStream>> for TlsClientTransport {
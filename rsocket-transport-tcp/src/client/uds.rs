@@ -29,6 +29,16 @@ impl Transport for UnixClientTransport {
             },
         }
     }
+
+    fn peer(&self) -> Option<String> {
+        match &self.connector {
+            Connector::Direct(socket) => socket
+                .peer_addr()
+                .ok()
+                .and_then(|it| it.as_pathname().map(|it| it.display().to_string())),
+            Connector::Lazy(addr) => Some(addr.clone()),
+        }
+    }
 }
 
 impl From<UnixStream> for UnixClientTransport {
@@ -1,5 +1,4 @@
-use futures::{SinkExt, StreamExt};
-use rsocket_rust::error::RSocketError;
+use futures::StreamExt;
 use rsocket_rust::transport::{Connection, FrameSink, FrameStream};
 use tokio::net::TcpStream;
 use tokio_native_tls::TlsStream;
@@ -15,10 +14,7 @@ pub struct TlsConnection {
 impl Connection for TlsConnection {
     fn split(self) -> (Box<FrameSink>, Box<FrameStream>) {
         let (sink, stream) = Framed::new(self.stream, LengthBasedFrameCodec).split();
-        (
-            Box::new(sink.sink_map_err(|e| RSocketError::Other(e.into()))),
-            Box::new(stream.map(|it| it.map_err(|e| RSocketError::Other(e.into())))),
-        )
+        (Box::new(sink), Box::new(stream))
     }
 }
 
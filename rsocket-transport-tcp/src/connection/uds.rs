@@ -1,5 +1,4 @@
-use futures::{SinkExt, StreamExt};
-use rsocket_rust::error::RSocketError;
+use futures::StreamExt;
 use rsocket_rust::transport::{Connection, FrameSink, FrameStream};
 use tokio::net::UnixStream;
 use tokio_util::codec::Framed;
@@ -14,10 +13,7 @@ pub struct UnixConnection {
 impl Connection for UnixConnection {
     fn split(self) -> (Box<FrameSink>, Box<FrameStream>) {
         let (sink, stream) = Framed::new(self.stream, LengthBasedFrameCodec).split();
-        (
-            Box::new(sink.sink_map_err(|e| RSocketError::Other(e.into()))),
-            Box::new(stream.map(|it| it.map_err(|e| RSocketError::Other(e.into())))),
-        )
+        (Box::new(sink), Box::new(stream))
     }
 }
 
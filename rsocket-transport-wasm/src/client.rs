@@ -52,8 +52,10 @@ impl WebsocketClientTransport {
                 let raw: Vec<u8> = Uint8Array::new(&data).to_vec();
                 // Use data...
                 let mut bf = BytesMut::from(&raw[..]);
-                let msg = Frame::decode(&mut bf).unwrap();
-                incoming.try_send(msg).unwrap();
+                match Frame::decode(&mut bf) {
+                    Ok(msg) => incoming.try_send(msg).unwrap(),
+                    Err(e) => log::error!("decode frame failed: {}", e),
+                }
             })
         };
         let onerror = {
@@ -40,6 +40,13 @@ impl Transport for WebsocketClientTransport {
             },
         }
     }
+
+    fn peer(&self) -> Option<String> {
+        match &self.connector {
+            Connector::Direct(stream) => stream.peer_addr().ok().map(|it| it.to_string()),
+            Connector::Lazy(u) => Some(u.to_string()),
+        }
+    }
 }
 
 impl From<TcpStream> for WebsocketClientTransport {
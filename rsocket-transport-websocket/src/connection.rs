@@ -70,10 +70,8 @@ impl Connection for WebsocketConnection {
                     let raw = msg.into_data();
                     let mut bf = BytesMut::new();
                     bf.put_slice(&raw[..]);
-                    match Frame::decode(&mut bf) {
-                        Ok(frame) => Ok(frame),
-                        Err(e) => Err(RSocketError::Other(e.into())),
-                    }
+                    Frame::decode(&mut bf)
+                        .map_err(|e| e.downcast().unwrap_or_else(RSocketError::Other))
                 }
                 Err(e) => Err(RSocketError::Other(e.into())),
             })),
@@ -5,23 +5,34 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::{future, FutureExt, Sink, SinkExt, Stream, StreamExt};
-use tokio::sync::{mpsc, Mutex, Notify};
-
-use crate::error::{RSocketError, ERR_CONN_CLOSED};
-use crate::frame::{self, Frame};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::time::Instant;
+
+use super::interceptor::Interceptors;
+use super::resume::{self, Disconnect, ResumeCache};
+use crate::error::{self, RSocketError};
+use crate::frame::{self, Body, Frame};
+use crate::metrics::MetricsRecorder;
 use crate::payload::{Payload, SetupPayload, SetupPayloadBuilder};
 use crate::runtime;
-use crate::spi::{ClientResponder, Flux, RSocket};
+use crate::spi::{ClientResponder, Flux, LimitRate, RSocket, RSocketInterceptor, RequestOverflow};
+use crate::trace;
 use crate::transport::{
-    self, Connection, DuplexSocket, FrameSink, FrameStream, Splitter, Transport,
+    self, Connection, ConnectionLayers, DuplexSocket, FrameInterceptor, FrameSink, FrameStream,
+    Splitter, Transport,
 };
+use crate::utils::EmptyRSocket;
 use crate::Result;
 
 #[derive(Clone)]
 pub struct Client {
-    closed: Arc<Notify>,
+    closed: watch::Receiver<bool>,
     socket: DuplexSocket,
+    // the socket wrapped by the requester interceptors.
+    requester: Arc<dyn RSocket>,
+    interceptors: Arc<Interceptors>,
     closing: mpsc::Sender<()>,
 }
 
@@ -29,8 +40,18 @@ pub struct ClientBuilder<T, C> {
     transport: Option<T>,
     setup: SetupPayloadBuilder,
     responder: Option<ClientResponder>,
-    closer: Option<Box<dyn FnMut() + Send + Sync>>,
+    closer: Option<Box<dyn FnMut(Option<&RSocketError>) + Send + Sync>>,
     mtu: usize,
+    max_reassembled_size: Option<usize>,
+    limit_rate: LimitRate,
+    lease: bool,
+    max_streams: Option<usize>,
+    max_requests: Option<(usize, RequestOverflow)>,
+    timeout: Option<Duration>,
+    interceptors: Interceptors,
+    layers: ConnectionLayers,
+    reconnect: Option<Box<dyn Fn() -> T + Send + Sync>>,
+    session_duration: Duration,
     _c: PhantomData<C>,
 }
 
@@ -46,6 +67,16 @@ where
             setup: SetupPayload::builder(),
             closer: None,
             mtu: 0,
+            max_reassembled_size: None,
+            limit_rate: LimitRate::default(),
+            lease: false,
+            max_streams: None,
+            max_requests: None,
+            timeout: None,
+            interceptors: Interceptors::default(),
+            layers: ConnectionLayers::default(),
+            reconnect: None,
+            session_duration: resume::DEFAULT_SESSION_DURATION,
             _c: PhantomData,
         }
     }
@@ -58,6 +89,69 @@ where
         self
     }
 
+    /// Limits the size of fragmented payloads received from the server, 16MB by default. Streams
+    /// whose reassembled payload exceeds the limit are terminated with ERROR[INVALID].
+    pub fn max_reassembled_size(mut self, size: usize) -> Self {
+        self.max_reassembled_size = Some(size);
+        self
+    }
+
+    /// Sets the default REQUEST_N replenishment policy of request_stream and request_channel.
+    pub fn limit_rate(mut self, high_tide: u32, low_tide: u32) -> Self {
+        self.limit_rate = LimitRate::new(high_tide, low_tide);
+        self
+    }
+
+    /// Requires leases from the server, requests are rejected with `RSocketError::RequestRejected`
+    /// unless a valid lease is held.
+    pub fn lease(mut self) -> Self {
+        self.lease = true;
+        self
+    }
+
+    /// Limits the concurrent streams requested by the server, requests beyond the limit are
+    /// rejected with ERROR[REJECTED].
+    pub fn max_concurrent_streams(mut self, max: usize) -> Self {
+        self.max_streams = Some(max);
+        self
+    }
+
+    /// Limits the concurrent requests sent to the server, `overflow` decides whether requests
+    /// beyond the limit wait for a permit or fail at once. A limited request_stream or
+    /// request_channel is sent once its result is polled.
+    pub fn max_concurrent_requests(mut self, max: usize, overflow: RequestOverflow) -> Self {
+        self.max_requests = Some((max, overflow));
+        self
+    }
+
+    /// Sets the default timeout of request_response, a request which has not been answered within
+    /// the timeout fails with `RSocketError::RequestTimeout` and is cancelled. Streams may last as
+    /// long as the connection, so they only expire with `Client::request_stream_with_timeout` or
+    /// `Client::request_channel_with_timeout`.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Enables session resumption with the token, `reconnect` creates a new transport each time
+    /// the connection drops, and the unacknowledged frames will be sent again once resumed.
+    pub fn resume<A, F>(mut self, token: A, reconnect: F) -> Self
+    where
+        A: Into<Vec<u8>>,
+        F: 'static + Send + Sync + Fn() -> T,
+    {
+        let token = Bytes::from(token.into());
+        self.setup = self.setup.set_resume_token(Some(token));
+        self.reconnect = Some(Box::new(reconnect));
+        self
+    }
+
+    /// Sets how long the client keeps trying to resume a dropped session, 60 seconds by default.
+    pub fn resume_session_duration(mut self, duration: Duration) -> Self {
+        self.session_duration = duration;
+        self
+    }
+
     pub fn transport(mut self, transport: T) -> Self {
         self.transport = Some(transport);
         self
@@ -103,7 +197,43 @@ where
         self
     }
 
-    pub fn on_close(mut self, callback: Box<dyn FnMut() + Sync + Send>) -> Self {
+    /// Wraps the requester of the client, interceptors are applied in the order of registration.
+    pub fn requester_interceptor(mut self, interceptor: RSocketInterceptor) -> Self {
+        self.interceptors.for_requester(interceptor);
+        self
+    }
+
+    /// Wraps the responder which serves requests from the server, interceptors are applied in the
+    /// order of registration.
+    pub fn responder_interceptor(mut self, interceptor: RSocketInterceptor) -> Self {
+        self.interceptors.for_responder(interceptor);
+        self
+    }
+
+    /// Intercepts the frames of the connection, including those of resumed connections. The
+    /// first registered interceptor is the closest to the wire.
+    pub fn frame_interceptor(mut self, interceptor: Box<dyn FrameInterceptor>) -> Self {
+        self.layers.frame_interceptors.push(Arc::from(interceptor));
+        self
+    }
+
+    /// Records the metrics of the client to the recorder, e.g. an `InMemoryMetrics`.
+    pub fn metrics(mut self, recorder: Arc<dyn MetricsRecorder>) -> Self {
+        self.layers.metrics = Some(recorder);
+        self
+    }
+
+    pub fn on_close(mut self, mut callback: Box<dyn FnMut() + Sync + Send>) -> Self {
+        self.closer = Some(Box::new(move |_| callback()));
+        self
+    }
+
+    /// Like `on_close`, but also receives the reason, e.g. the keepalive lifetime has expired.
+    /// The reason is None if the connection has been closed normally.
+    pub fn on_close_with(
+        mut self,
+        callback: Box<dyn FnMut(Option<&RSocketError>) + Sync + Send>,
+    ) -> Self {
         self.closer = Some(callback);
         self
     }
@@ -124,153 +254,380 @@ where
         };
 
         let (snd_tx, mut snd_rx) = mpsc::unbounded_channel::<Frame>();
-        let cloned_snd_tx = snd_tx.clone();
-        let mut socket = DuplexSocket::new(1, snd_tx, splitter).await;
+        let mut socket = DuplexSocket::new(1, snd_tx, splitter, self.limit_rate).await;
+        if self.lease {
+            socket.honor_lease();
+        }
+        if let Some(max) = self.max_streams {
+            socket.limit_streams(max);
+        }
+        if let Some(size) = self.max_reassembled_size {
+            socket.set_max_reassembled_size(size);
+        }
+        if let Some((max, overflow)) = self.max_requests {
+            socket.limit_requests(max, overflow);
+        }
+        if let Some(timeout) = self.timeout {
+            socket.set_request_timeout(timeout);
+        }
+        if let Some(recorder) = &self.layers.metrics {
+            socket.set_metrics(recorder.clone());
+        }
+        let span = trace::connection("client", tp.peer());
+        socket.set_span(span.clone());
 
         let mut cloned_socket = socket.clone();
 
-        if let Some(f) = self.responder {
-            let responder = f();
-            socket.bind_responder(responder).await;
-        }
+        let responder: Box<dyn RSocket> = match self.responder {
+            Some(f) => f(),
+            None => Box::new(EmptyRSocket),
+        };
+        socket
+            .bind_responder(self.interceptors.responder(responder))
+            .await;
+        let requester = Arc::from(self.interceptors.requester(Box::new(socket.clone())));
+        let interceptors = Arc::new(self.interceptors);
 
         let conn = tp.connect().await?;
-        let (mut sink, mut stream) = conn.split();
+        let (sink, stream) = conn.split();
+        let layers = self.layers;
+        let (mut sink, mut stream) = layers.wrap(sink, stream);
 
         let setup = self.setup.build();
-
-        // begin write loop
         let tick_period = setup.keepalive_interval();
-        runtime::spawn(async move {
-            loop {
-                // send keepalive if timeout
-                match tokio::time::timeout(tick_period, snd_rx.recv()).await {
-                    Ok(Some(frame)) => {
-                        if let frame::Body::Error(e) = frame.get_body_ref() {
-                            if e.get_code() == ERR_CONN_CLOSED {
-                                break;
-                            }
-                        }
-                        if let Err(e) = sink.send(frame).await {
-                            error!("write frame failed: {}", e);
-                            break;
-                        }
-                    }
-                    Ok(None) => break,
-                    Err(_) => {
-                        // keepalive
-                        let keepalive_frame =
-                            frame::Keepalive::builder(0, Frame::FLAG_RESPOND).build();
-                        if let Err(e) = sink.send(keepalive_frame).await {
-                            error!("write frame failed: {}", e);
-                            break;
-                        }
-                    }
-                }
-            }
-        });
+        let lifetime = setup.keepalive_lifetime();
+        let token = setup.resume_token().cloned();
 
-        // begin read loop
         let closer = self.closer.take();
-        let close_notify = Arc::new(Notify::new());
-        let close_notify_clone = close_notify.clone();
+        let (closed_tx, closed_rx) = watch::channel(false);
         let (closing, mut closing_rx) = mpsc::channel::<()>(1);
 
         let (read_tx, mut read_rx) = mpsc::unbounded_channel::<Frame>();
-
-        // read frames from stream, then writes into channel
-        runtime::spawn(async move {
+        let (reason_tx, reason_rx) = oneshot::channel::<RSocketError>();
+        let (broken_tx, mut broken_rx) = oneshot::channel::<()>();
+
+        // pump frames until the connection drops, then try to resume the session.
+        let reconnect = self.reconnect.take();
+        let session_duration = self.session_duration;
+        let connection = span.clone();
+        let pumping = async move {
+            let mut cache = reconnect.as_ref().map(|_| ResumeCache::default());
             loop {
-                tokio::select! {
-                    res = stream.next() => {
-                        match res {
-                            Some(next) => match next {
-                                Ok(frame) => {
-                                    if let Err(e) = read_tx.send(frame) {
-                                        error!("forward frame failed: {}", e);
-                                        break;
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("read frame failed: {}", e);
-                                    break;
-                                }
-                            }
-                            None => break,
+                let res = tokio::select! {
+                    res = resume::pump(
+                        &mut sink,
+                        &mut stream,
+                        &mut snd_rx,
+                        &read_tx,
+                        cache.as_mut(),
+                        Some(tick_period),
+                        Some(lifetime),
+                    ) => res,
+                    _ = closing_rx.recv() => Disconnect::Closed,
+                    _ = &mut broken_rx => Disconnect::Closed,
+                };
+                match res {
+                    Disconnect::Closed => {
+                        resume::flush_and_close(&mut sink, &mut snd_rx).await;
+                        break;
+                    }
+                    Disconnect::Failed(errmsg) => {
+                        let reason = RSocketError::ConnectionException(errmsg);
+                        trace::error(&connection, &reason);
+                        let _ = reason_tx.send(reason);
+                        break;
+                    }
+                    _ => (),
+                }
+                let (reconnect, token, cache) = match (&reconnect, &token, cache.as_mut()) {
+                    (Some(a), Some(b), Some(c)) => (a, b, c),
+                    _ => {
+                        if res == Disconnect::Expired {
+                            let reason =
+                                RSocketError::ConnectionException("keepalive timeout".into());
+                            trace::error(&connection, &reason);
+                            let _ = reason_tx.send(reason);
                         }
+                        break;
                     }
-                    _ = closing_rx.recv() => {
-                        break
+                };
+                let resumed = tokio::select! {
+                    res = Self::resume_session(
+                        reconnect,
+                        &layers,
+                        token,
+                        session_duration,
+                        cache,
+                    ) => res,
+                    _ = closing_rx.recv() => None,
+                    _ = &mut broken_rx => None,
+                };
+                match resumed {
+                    Some((a, b)) => {
+                        sink = a;
+                        stream = b;
                     }
+                    None => break,
                 }
             }
-        });
+        };
+        runtime::spawn(trace::instrument(pumping, &span));
 
         // process frames
-        runtime::spawn(async move {
+        let connection = span.clone();
+        let processing = async move {
+            let mut reason = None;
             while let Some(next) = read_rx.recv().await {
                 if let Err(e) = cloned_socket.dispatch(next, None).await {
                     error!("dispatch frame failed: {}", e);
+                    trace::error(&connection, &e);
+                    reason = Some(
+                        e.downcast::<RSocketError>()
+                            .unwrap_or_else(|e| RSocketError::WithDescription(e.to_string())),
+                    );
+                    // the connection is broken, close it rather than resuming.
+                    let _ = broken_tx.send(());
                     break;
                 }
             }
-
-            // workaround: send a notify frame that the connection has been closed.
-            let close_frame = frame::Error::builder(0, 0)
-                .set_code(ERR_CONN_CLOSED)
-                .build();
-            if let Err(e) = cloned_snd_tx.send(close_frame) {
-                debug!("send close notify frame failed: {}", e);
+            if reason.is_none() {
+                reason = reason_rx.await.ok();
             }
 
+            // the connection is over, fail the outstanding and later requests.
+            cloned_socket.close();
+            let errmsg = reason.as_ref().map(|it| it.to_string());
+            cloned_socket.terminate(|| match &errmsg {
+                Some(it) => RSocketError::ConnectionException(it.clone()),
+                None => RSocketError::ConnectionClosed("connection closed".into()),
+            });
+
             // notify client closed
-            close_notify_clone.notify_one();
+            let _ = closed_tx.send(true);
 
             // invoke on_close handler
             if let Some(mut invoke) = closer {
-                invoke();
+                invoke(reason.as_ref());
             }
-        });
+        };
+        runtime::spawn(trace::instrument(processing, &span));
 
         socket.setup(setup).await;
 
-        Ok(Client::new(socket, close_notify, closing))
+        Ok(Client::new(
+            socket,
+            requester,
+            interceptors,
+            closed_rx,
+            closing,
+        ))
+    }
+
+    async fn resume_session(
+        reconnect: &(dyn Fn() -> T + Send + Sync),
+        layers: &ConnectionLayers,
+        token: &Bytes,
+        session_duration: Duration,
+        cache: &mut ResumeCache,
+    ) -> Option<(Box<FrameSink>, Box<FrameStream>)> {
+        let deadline = Instant::now() + session_duration;
+        let mut backoff = Duration::from_millis(100);
+        while Instant::now() < deadline {
+            let resuming = Self::try_resume(reconnect(), layers, token, cache);
+            match tokio::time::timeout_at(deadline, resuming).await {
+                Ok(Ok(it)) => {
+                    info!("session has been resumed");
+                    return Some(it);
+                }
+                Ok(Err(e)) => {
+                    if let Some(RSocketError::RejectedResume(_)) = e.downcast_ref::<RSocketError>()
+                    {
+                        error!("resume session failed: {}", e);
+                        return None;
+                    }
+                    warn!("resume session failed: {}", e);
+                }
+                Err(_) => break,
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(5));
+        }
+        error!("resume session failed: timeout");
+        None
+    }
+
+    async fn try_resume(
+        tp: T,
+        layers: &ConnectionLayers,
+        token: &Bytes,
+        cache: &mut ResumeCache,
+    ) -> Result<(Box<FrameSink>, Box<FrameStream>)> {
+        let (sink, stream) = tp.connect().await?.split();
+        let (mut sink, mut stream) = layers.wrap(sink, stream);
+        let sending = frame::Resume::builder(0, 0)
+            .set_token(token.clone())
+            .set_last_received_server_position(cache.received_position())
+            .set_first_available_client_position(cache.first_available_position())
+            .build();
+        sink.send(sending).await?;
+        let position = match stream.next().await {
+            Some(Ok(next)) => match next.get_body() {
+                Body::ResumeOK(v) => v.get_position(),
+                Body::Error(v) => {
+                    let desc = v.get_data_utf8().unwrap_or_default().to_owned();
+                    return Err(RSocketError::must_new_from_code(v.get_code(), desc).into());
+                }
+                _ => return Err(RSocketError::WithDescription("expect RESUME_OK".into()).into()),
+            },
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err(RSocketError::ConnectionClosed("resume".into()).into()),
+        };
+        let frames = match cache.replay(position) {
+            Some(it) => it,
+            None => {
+                let errmsg = "frames have been released";
+                let sending = frame::Error::builder(0, 0)
+                    .set_code(error::ERR_CONN_FAILED)
+                    .set_data(Bytes::from(errmsg))
+                    .build();
+                sink.send(sending).await?;
+                return Err(RSocketError::RejectedResume(errmsg.into()).into());
+            }
+        };
+        for next in frames {
+            sink.send(next).await?;
+        }
+        Ok((sink, stream))
     }
 }
 
 impl Client {
-    fn new(socket: DuplexSocket, closed: Arc<Notify>, closing: mpsc::Sender<()>) -> Client {
+    fn new(
+        socket: DuplexSocket,
+        requester: Arc<dyn RSocket>,
+        interceptors: Arc<Interceptors>,
+        closed: watch::Receiver<bool>,
+        closing: mpsc::Sender<()>,
+    ) -> Client {
         Client {
             socket,
+            requester,
+            interceptors,
             closed,
             closing,
         }
     }
 
     pub async fn wait_for_close(self) {
-        self.closed.notified().await
+        self.wait_closed().await
+    }
+
+    /// Closes the connection immediately, outstanding streams are cancelled.
+    pub async fn close(&self) {
+        self.close_gracefully(Duration::from_secs(0)).await
+    }
+
+    /// Stops sending new requests and waits for outstanding streams to finish, those which are
+    /// still in-flight after `timeout` will be cancelled. Then sends ERROR[CONNECTION_CLOSE] and
+    /// resolves once the transport has been shut down.
+    pub async fn close_gracefully(&self, timeout: Duration) {
+        self.socket.close();
+        let deadline = Instant::now() + timeout;
+        tokio::select! {
+            _ = self.socket.drained(deadline) => (),
+            _ = self.wait_closed() => return,
+        }
+        let errmsg = "client closed";
+        self.socket
+            .terminate(|| RSocketError::ConnectionClosed(errmsg.into()));
+        self.socket.send_error(error::ERR_CONN_CLOSED, errmsg);
+        let _ = self.closing.send(()).await;
+        self.wait_closed().await
+    }
+
+    async fn wait_closed(&self) {
+        let mut closed = self.closed.clone();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    /// Request-Stream which replenishes REQUEST_N with the given policy.
+    pub fn request_stream_with(
+        &self,
+        req: Payload,
+        limit_rate: LimitRate,
+    ) -> Flux<Result<Payload>> {
+        self.requester_with(limit_rate, None).request_stream(req)
+    }
+
+    /// Request-Channel which replenishes REQUEST_N with the given policy.
+    pub fn request_channel_with(
+        &self,
+        reqs: Flux<Result<Payload>>,
+        limit_rate: LimitRate,
+    ) -> Flux<Result<Payload>> {
+        self.requester_with(limit_rate, None).request_channel(reqs)
+    }
+
+    /// Request-Response which overrides the default timeout of the client.
+    pub async fn request_response_with_timeout(
+        &self,
+        req: Payload,
+        timeout: Duration,
+    ) -> Result<Option<Payload>> {
+        self.requester_with(self.socket.limit_rate(), Some(timeout))
+            .request_response(req)
+            .await
+    }
+
+    /// Request-Stream which fails with `RSocketError::RequestTimeout` and is cancelled unless it
+    /// terminates within the timeout.
+    pub fn request_stream_with_timeout(
+        &self,
+        req: Payload,
+        timeout: Duration,
+    ) -> Flux<Result<Payload>> {
+        self.requester_with(self.socket.limit_rate(), Some(timeout))
+            .request_stream(req)
+    }
+
+    /// Request-Channel which fails with `RSocketError::RequestTimeout` and is cancelled unless it
+    /// terminates within the timeout.
+    pub fn request_channel_with_timeout(
+        &self,
+        reqs: Flux<Result<Payload>>,
+        timeout: Duration,
+    ) -> Flux<Result<Payload>> {
+        self.requester_with(self.socket.limit_rate(), Some(timeout))
+            .request_channel(reqs)
+    }
+
+    /// Wraps a requester which overrides the defaults of the client with the requester
+    /// interceptors, so a request which overrides them is intercepted like any other.
+    fn requester_with(&self, limit_rate: LimitRate, timeout: Option<Duration>) -> Box<dyn RSocket> {
+        let socket = self.socket.with_overrides(limit_rate, timeout);
+        self.interceptors.requester(Box::new(socket))
     }
 }
 
 #[async_trait]
 impl RSocket for Client {
     async fn metadata_push(&self, req: Payload) -> Result<()> {
-        self.socket.metadata_push(req).await
+        self.requester.metadata_push(req).await
     }
 
     async fn fire_and_forget(&self, req: Payload) -> Result<()> {
-        self.socket.fire_and_forget(req).await
+        self.requester.fire_and_forget(req).await
     }
 
     async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
-        self.socket.request_response(req).await
+        self.requester.request_response(req).await
     }
 
     fn request_stream(&self, req: Payload) -> Flux<Result<Payload>> {
-        self.socket.request_stream(req)
+        self.requester.request_stream(req)
     }
 
     fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
-        self.socket.request_channel(reqs)
+        self.requester.request_channel(reqs)
     }
 }
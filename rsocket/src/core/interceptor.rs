@@ -0,0 +1,41 @@
+use crate::spi::{RSocket, RSocketInterceptor, ServerResponder};
+use crate::utils::EmptyRSocket;
+
+/// Interceptors registered on a builder. They are applied in the order of registration, so the
+/// last registered one is the outermost.
+#[derive(Default)]
+pub(crate) struct Interceptors {
+    requester: Vec<RSocketInterceptor>,
+    responder: Vec<RSocketInterceptor>,
+}
+
+impl Interceptors {
+    pub(crate) fn for_requester(&mut self, interceptor: RSocketInterceptor) {
+        self.requester.push(interceptor);
+    }
+
+    pub(crate) fn for_responder(&mut self, interceptor: RSocketInterceptor) {
+        self.responder.push(interceptor);
+    }
+
+    pub(crate) fn requester(&self, rsocket: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        self.requester.iter().fold(rsocket, |it, wrap| wrap(it))
+    }
+
+    pub(crate) fn responder(&self, rsocket: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        self.responder.iter().fold(rsocket, |it, wrap| wrap(it))
+    }
+
+    /// Wraps the acceptor of a server, so the requester handed to it and the responder returned by
+    /// it are both intercepted.
+    pub(crate) fn acceptor(self, acceptor: Option<ServerResponder>) -> Option<ServerResponder> {
+        if self.requester.is_empty() && self.responder.is_empty() {
+            return acceptor;
+        }
+        let acceptor = acceptor.unwrap_or_else(|| Box::new(|_, _| Ok(Box::new(EmptyRSocket))));
+        Some(Box::new(move |setup, requester| {
+            let responder = acceptor(setup, self.requester(requester))?;
+            Ok(self.responder(responder))
+        }))
+    }
+}
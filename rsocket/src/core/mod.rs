@@ -1,7 +1,9 @@
 mod client;
 mod factory;
+mod interceptor;
+mod resume;
 mod server;
 
 pub use client::{Client, ClientBuilder};
 pub use factory::RSocketFactory;
-pub use server::ServerBuilder;
+pub use server::{ServerBuilder, ShutdownHandle};
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::error::{self, RSocketError};
+use crate::frame::{self, Body, Frame};
+use crate::transport::{FrameSink, FrameStream};
+use crate::utils::Writeable;
+
+pub(crate) const DEFAULT_SESSION_DURATION: Duration = Duration::from_secs(60);
+
+/// Outbound frames which have not been acknowledged by the peer yet.
+///
+/// Positions are implied by the lengths of the resumable frames, which are all frames with a
+/// non-zero stream id.
+#[derive(Debug, Default)]
+pub(crate) struct ResumeCache {
+    frames: VecDeque<Frame>,
+    first_available: u64,
+    sent: u64,
+    received: u64,
+}
+
+impl ResumeCache {
+    pub(crate) fn on_sent(&mut self, frame: &Frame) {
+        if frame.get_stream_id() != 0 {
+            self.sent += frame.len() as u64;
+            self.frames.push_back(frame.clone());
+        }
+    }
+
+    pub(crate) fn on_received(&mut self, frame: &Frame) {
+        match frame.get_body_ref() {
+            Body::Keepalive(v) => self.release(v.get_last_received_position()),
+            _ if frame.get_stream_id() != 0 => self.received += frame.len() as u64,
+            _ => (),
+        }
+    }
+
+    pub(crate) fn received_position(&self) -> u64 {
+        self.received
+    }
+
+    pub(crate) fn first_available_position(&self) -> u64 {
+        self.first_available
+    }
+
+    /// Returns the frames which should be sent again after the peer has received `position`,
+    /// or None if some of them have been released already.
+    pub(crate) fn replay(&mut self, position: u64) -> Option<Vec<Frame>> {
+        if position > self.sent {
+            return None;
+        }
+        self.release(position);
+        if position != self.first_available {
+            return None;
+        }
+        Some(self.frames.iter().cloned().collect())
+    }
+
+    fn release(&mut self, position: u64) {
+        while let Some(front) = self.frames.front() {
+            let end = self.first_available + front.len() as u64;
+            if end > position {
+                break;
+            }
+            self.first_available = end;
+            self.frames.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Disconnect {
+    /// The connection has been dropped, the session may be resumed.
+    Dropped,
+    /// The socket has been closed.
+    Closed,
+    /// Nothing has been received from the peer within the keepalive lifetime.
+    Expired,
+    /// The peer has sent a malformed frame, the connection has been closed with
+    /// ERROR[CONNECTION_ERROR].
+    Failed(String),
+}
+
+/// Pumps frames between a connection and the socket until the connection drops.
+///
+/// Keepalive frames will be sent every `tick_period`, and the connection expires if nothing has
+/// been received for `lifetime`. An expired connection which can not be resumed is closed with
+/// ERROR[CONNECTION_ERROR].
+pub(crate) async fn pump(
+    sink: &mut Box<FrameSink>,
+    stream: &mut Box<FrameStream>,
+    outbound: &mut mpsc::UnboundedReceiver<Frame>,
+    inbound: &mpsc::UnboundedSender<Frame>,
+    mut cache: Option<&mut ResumeCache>,
+    tick_period: Option<Duration>,
+    lifetime: Option<Duration>,
+) -> Disconnect {
+    let ticking = tick_period.is_some();
+    let tick_period = tick_period.unwrap_or_default();
+    let mut deadline = Instant::now() + tick_period;
+    let expiring = lifetime.is_some();
+    let lifetime = lifetime.unwrap_or_default();
+    let mut last_received = Instant::now();
+    loop {
+        tokio::select! {
+            next = outbound.recv() => {
+                let mut frame = match next {
+                    Some(it) => it,
+                    None => return Disconnect::Closed,
+                };
+                if let Some(cache) = cache.as_mut() {
+                    frame = acknowledge(frame, cache);
+                    cache.on_sent(&frame);
+                }
+                if let Err(e) = sink.send(frame).await {
+                    error!("write frame failed: {}", e);
+                    return Disconnect::Dropped;
+                }
+            }
+            next = stream.next() => {
+                let frame = match next {
+                    Some(Ok(it)) => it,
+                    Some(Err(RSocketError::IgnoredFrame(typ))) => {
+                        debug!("skip frame of unknown type {}", typ);
+                        continue;
+                    }
+                    Some(Err(e)) if e.is_malformed_frame() => {
+                        error!("read frame failed: {}", e);
+                        let sending = frame::Error::builder(0, 0)
+                            .set_code(error::ERR_CONN_FAILED)
+                            .set_data(Bytes::from(e.to_string()))
+                            .build();
+                        if let Err(e) = sink.send(sending).await {
+                            error!("write frame failed: {}", e);
+                        }
+                        if let Err(e) = sink.close().await {
+                            error!("close connection failed: {}", e);
+                        }
+                        return Disconnect::Failed(e.to_string());
+                    }
+                    Some(Err(e)) => {
+                        error!("read frame failed: {}", e);
+                        return Disconnect::Dropped;
+                    }
+                    None => return Disconnect::Dropped,
+                };
+                last_received = Instant::now();
+                if let Some(cache) = cache.as_mut() {
+                    cache.on_received(&frame);
+                }
+                if let Err(e) = inbound.send(frame) {
+                    error!("forward frame failed: {}", e);
+                    return Disconnect::Closed;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline), if ticking => {
+                let mut keepalive = frame::Keepalive::builder(0, Frame::FLAG_RESPOND);
+                if let Some(cache) = cache.as_ref() {
+                    keepalive = keepalive.set_last_received_position(cache.received_position());
+                }
+                if let Err(e) = sink.send(keepalive.build()).await {
+                    error!("write frame failed: {}", e);
+                    return Disconnect::Dropped;
+                }
+                deadline = Instant::now() + tick_period;
+            }
+            _ = tokio::time::sleep_until(last_received + lifetime), if expiring => {
+                warn!("no frame has been received in {:?}", lifetime);
+                if cache.is_none() {
+                    let sending = frame::Error::builder(0, 0)
+                        .set_code(error::ERR_CONN_FAILED)
+                        .set_data(Bytes::from("keepalive timeout"))
+                        .build();
+                    if let Err(e) = sink.send(sending).await {
+                        error!("write frame failed: {}", e);
+                    }
+                }
+                return Disconnect::Expired;
+            }
+        }
+    }
+}
+
+/// Sends the frames which have been queued, then closes the connection.
+pub(crate) async fn flush_and_close(
+    sink: &mut Box<FrameSink>,
+    outbound: &mut mpsc::UnboundedReceiver<Frame>,
+) {
+    while let Ok(next) = outbound.try_recv() {
+        if let Err(e) = sink.send(next).await {
+            error!("write frame failed: {}", e);
+            return;
+        }
+    }
+    if let Err(e) = sink.close().await {
+        error!("close connection failed: {}", e);
+    }
+}
+
+/// Fills the last received position into keepalive frames.
+fn acknowledge(frame: Frame, cache: &ResumeCache) -> Frame {
+    if !matches!(frame.get_body_ref(), Body::Keepalive(_)) {
+        return frame;
+    }
+    let flag = frame.get_flag();
+    let mut bu =
+        frame::Keepalive::builder(0, flag).set_last_received_position(cache.received_position());
+    if let Body::Keepalive(v) = frame.get_body() {
+        if let (Some(b), _) = v.split() {
+            bu = bu.set_data(b);
+        }
+    }
+    bu.build()
+}
@@ -4,27 +4,96 @@ use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
+use bytes::Bytes;
+use dashmap::{mapref::entry::Entry, DashMap};
 use futures::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
 
-use crate::error::RSocketError;
-use crate::frame::{self, Frame};
+use super::interceptor::Interceptors;
+use super::resume::{self, Disconnect, ResumeCache};
+use crate::error::{self, RSocketError};
+use crate::frame::{self, Body, Frame};
+use crate::metrics::MetricsRecorder;
 use crate::payload::SetupPayload;
 use crate::runtime;
-use crate::spi::{RSocket, ServerResponder};
-use crate::transport::{Connection, DuplexSocket, ServerTransport, Splitter, Transport, MIN_MTU};
+use crate::spi::{LeaseStrategy, LimitRate, RSocket, RSocketInterceptor, ServerResponder};
+use crate::trace;
+use crate::transport::{
+    self, Connection, ConnectionLayers, DuplexSocket, FrameInterceptor, FrameSink, FrameStream,
+    ServerTransport, Splitter, Transport, MIN_MTU,
+};
 use crate::utils::EmptyRSocket;
 use crate::Result;
 
+/// A new connection which resumes a detached session.
+type Attach = (Box<FrameSink>, Box<FrameStream>, frame::Resume);
+
+/// The deadline of a graceful shutdown, None until the shutdown begins.
+type Deadline = watch::Receiver<Option<Instant>>;
+
+/// Detached sessions waiting to be resumed, keyed by resume tokens.
+#[derive(Clone)]
+struct Sessions {
+    inner: Arc<DashMap<Bytes, mpsc::UnboundedSender<Attach>>>,
+    duration: Duration,
+}
+
+/// Options of the socket of each connection.
+#[derive(Debug, Clone, Copy)]
+struct SocketOptions {
+    mtu: usize,
+    max_reassembled_size: Option<usize>,
+    limit_rate: LimitRate,
+    max_streams: Option<usize>,
+}
+
 pub struct ServerBuilder<T, C> {
     transport: Option<T>,
     on_setup: Option<ServerResponder>,
+    interceptors: Interceptors,
+    layers: ConnectionLayers,
     start_handler: Option<Box<dyn FnMut() + Send + Sync>>,
     mtu: usize,
+    max_reassembled_size: Option<usize>,
+    limit_rate: LimitRate,
+    lease: Option<Arc<LeaseStrategy>>,
+    max_streams: Option<usize>,
+    resume: Option<Duration>,
+    shutdown: Option<ShutdownHandle>,
     _c: PhantomData<C>,
 }
 
+/// Shuts down a server gracefully, see `ServerBuilder::shutdown_handle`.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    deadline: Arc<watch::Sender<Option<Instant>>>,
+    // the number of servers which are still serving with the handle.
+    running: Arc<watch::Sender<usize>>,
+}
+
+/// Held by `serve` until it returns, whichever way it returns.
+struct Running(Option<ShutdownHandle>);
+
+impl Running {
+    fn new(shutdown: Option<ShutdownHandle>) -> Running {
+        if let Some(it) = &shutdown {
+            it.running.send_modify(|n| *n += 1);
+        }
+        Running(shutdown)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(it) = &self.0 {
+            it.running.send_modify(|n| *n -= 1);
+        }
+    }
+}
+
 impl<T, C> ServerBuilder<T, C>
 where
     T: Send + Sync + ServerTransport<Item = C>,
@@ -34,8 +103,16 @@ where
         ServerBuilder {
             transport: None,
             on_setup: None,
+            interceptors: Interceptors::default(),
+            layers: ConnectionLayers::default(),
             start_handler: None,
             mtu: 0,
+            max_reassembled_size: None,
+            limit_rate: LimitRate::default(),
+            lease: None,
+            max_streams: None,
+            resume: None,
+            shutdown: None,
             _c: PhantomData,
         }
     }
@@ -47,11 +124,84 @@ where
         self
     }
 
+    /// Limits the size of fragmented payloads received from each client, 16MB by default. Streams
+    /// whose reassembled payload exceeds the limit are terminated with ERROR[INVALID].
+    pub fn max_reassembled_size(mut self, size: usize) -> Self {
+        self.max_reassembled_size = Some(size);
+        self
+    }
+
+    /// Sets the default REQUEST_N replenishment policy of server-side requesters.
+    pub fn limit_rate(mut self, high_tide: u32, low_tide: u32) -> Self {
+        self.limit_rate = LimitRate::new(high_tide, low_tide);
+        self
+    }
+
+    /// Grants leases produced by the strategy to clients which require leasing.
+    pub fn lease(mut self, strategy: LeaseStrategy) -> Self {
+        self.lease = Some(Arc::new(strategy));
+        self
+    }
+
+    /// Limits the concurrent streams requested by each client, requests beyond the limit are
+    /// rejected with ERROR[REJECTED].
+    pub fn max_concurrent_streams(mut self, max: usize) -> Self {
+        self.max_streams = Some(max);
+        self
+    }
+
+    /// Keeps the sessions of dropped connections, so clients can resume them.
+    pub fn resume(mut self) -> Self {
+        self.resume = Some(resume::DEFAULT_SESSION_DURATION);
+        self
+    }
+
+    /// Sets how long a dropped session is kept for resumption, 60 seconds by default.
+    pub fn resume_session_duration(mut self, duration: Duration) -> Self {
+        self.resume = Some(duration);
+        self
+    }
+
+    /// Binds a handle which shuts down the server gracefully: new connections and requests are no
+    /// longer accepted, in-flight requests are served until the deadline, then every connection
+    /// is closed with ERROR[CONNECTION_CLOSE] and `serve` returns.
+    pub fn shutdown_handle(mut self, handle: ShutdownHandle) -> Self {
+        self.shutdown = Some(handle);
+        self
+    }
+
     pub fn acceptor(mut self, handler: ServerResponder) -> Self {
         self.on_setup = Some(handler);
         self
     }
 
+    /// Wraps the requester handed to the acceptor of each connection, interceptors are applied in
+    /// the order of registration.
+    pub fn requester_interceptor(mut self, interceptor: RSocketInterceptor) -> Self {
+        self.interceptors.for_requester(interceptor);
+        self
+    }
+
+    /// Wraps the responder returned by the acceptor of each connection, interceptors are applied
+    /// in the order of registration.
+    pub fn responder_interceptor(mut self, interceptor: RSocketInterceptor) -> Self {
+        self.interceptors.for_responder(interceptor);
+        self
+    }
+
+    /// Intercepts the frames of each connection. The first registered interceptor is the closest
+    /// to the wire.
+    pub fn frame_interceptor(mut self, interceptor: Box<dyn FrameInterceptor>) -> Self {
+        self.layers.frame_interceptors.push(Arc::from(interceptor));
+        self
+    }
+
+    /// Records the metrics of all connections to the recorder, e.g. an `InMemoryMetrics`.
+    pub fn metrics(mut self, recorder: Arc<dyn MetricsRecorder>) -> Self {
+        self.layers.metrics = Some(recorder);
+        self
+    }
+
     pub fn on_start(mut self, hanlder: Box<dyn FnMut() + Send + Sync>) -> Self {
         self.start_handler = Some(hanlder);
         self
@@ -69,10 +219,22 @@ where
     C: Send + Sync + Transport + 'static,
 {
     pub async fn serve(mut self) -> Result<()> {
+        let shutdown = self.shutdown.take();
+        let _running = Running::new(shutdown.clone());
         let mut server_transport = self.transport.take().expect("missing transport");
         // let acceptor = self.on_setup.map(|v| Acceptor::Generate(Arc::new(v)));
 
-        let mtu = self.mtu;
+        let options = SocketOptions {
+            mtu: self.mtu,
+            max_reassembled_size: self.max_reassembled_size,
+            limit_rate: self.limit_rate,
+            max_streams: self.max_streams,
+        };
+        let lease = self.lease.take();
+        let sessions = self.resume.map(|duration| Sessions {
+            inner: Arc::new(DashMap::new()),
+            duration,
+        });
 
         server_transport.start().await?;
 
@@ -80,81 +242,396 @@ where
             invoke();
         }
 
-        let acceptor = Arc::new(self.on_setup);
-        while let Some(next) = server_transport.next().await {
+        let mut deadline = shutdown.as_ref().map(|it| it.deadline.subscribe());
+        // every connection holds a sender, so all of them have finished once it is closed.
+        let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
+
+        let acceptor = Arc::new(self.interceptors.acceptor(self.on_setup));
+        loop {
+            let next = tokio::select! {
+                next = server_transport.next() => next,
+                _ = shutdown_requested(&mut deadline) => break,
+            };
             match next {
-                Ok(tp) => {
+                Some(Ok(tp)) => {
                     let acceptor = acceptor.clone();
+                    let layers = self.layers.clone();
+                    let lease = lease.clone();
+                    let sessions = sessions.clone();
+                    let deadline = deadline.clone();
+                    let done_tx = done_tx.clone();
                     runtime::spawn(async move {
-                        if let Err(e) = Self::on_transport(mtu, tp, acceptor).await {
+                        if let Err(e) = Self::on_transport(
+                            options, lease, sessions, deadline, tp, &layers, acceptor,
+                        )
+                        .await
+                        {
                             error!("handle transport failed: {}", e);
                         }
+                        drop(done_tx);
                     });
                 }
-                Err(e) => {
+                Some(Err(e)) => {
                     error!("accept next transport failed: {}", e);
                 }
+                None => return Ok(()),
             }
         }
+
+        // stop accepting, then wait for the connections to be closed.
+        drop(server_transport);
+        drop(done_tx);
+        let _ = done_rx.recv().await;
         Ok(())
     }
 
     #[inline]
-    async fn on_transport(mtu: usize, tp: C, acceptor: Arc<Option<ServerResponder>>) -> Result<()> {
+    async fn on_transport(
+        options: SocketOptions,
+        lease: Option<Arc<LeaseStrategy>>,
+        sessions: Option<Sessions>,
+        mut deadline: Option<Deadline>,
+        tp: C,
+        layers: &ConnectionLayers,
+        acceptor: Arc<Option<ServerResponder>>,
+    ) -> Result<()> {
         // Establish connection.
+        let span = trace::connection("server", tp.peer());
         let conn = tp.connect().await?;
-        let (mut writer, mut reader) = conn.split();
+        let (writer, reader) = conn.split();
+        let (mut writer, mut reader) = layers.wrap(writer, reader);
+
+        // The first frame decides whether to resume a session or to start a new one.
+        let first = tokio::select! {
+            next = reader.next() => match next {
+                Some(Ok(it)) => it,
+                Some(Err(e)) => {
+                    if e.is_malformed_frame() {
+                        Self::reject(&mut writer, error::ERR_CONN_FAILED, &e.to_string()).await;
+                    }
+                    return Err(e.into());
+                }
+                None => return Ok(()),
+            },
+            _ = shutdown_requested(&mut deadline) => return Ok(()),
+        };
+        let first = match first {
+            Frame {
+                body: Body::Resume(resume),
+                ..
+            } => {
+                Self::on_resume(sessions, writer, reader, resume).await;
+                return Ok(());
+            }
+            Frame {
+                body: Body::Setup(_),
+                ..
+            } => first,
+            _ => {
+                Self::reject(
+                    &mut writer,
+                    error::ERR_INVALID_SETUP,
+                    "expect SETUP or RESUME",
+                )
+                .await;
+                return Ok(());
+            }
+        };
+
+        let (token, lifetime) = match first.get_body_ref() {
+            Body::Setup(v) => {
+                // an invalid lifetime will be rejected by the socket.
+                let lifetime = Some(v.get_lifetime()).filter(|it| !it.is_zero());
+                (v.get_token().cloned(), lifetime)
+            }
+            _ => (None, None),
+        };
+        let attach = match (&token, &sessions) {
+            (Some(token), Some(sessions)) => match sessions.inner.entry(token.clone()) {
+                Entry::Vacant(v) => {
+                    let (attach_tx, attach_rx) = mpsc::unbounded_channel();
+                    v.insert(attach_tx);
+                    Some((attach_rx, sessions.duration))
+                }
+                Entry::Occupied(_) => {
+                    Self::reject(&mut writer, error::ERR_REJECT_SETUP, "resume token in use").await;
+                    return Ok(());
+                }
+            },
+            (Some(_), None) => {
+                Self::reject(
+                    &mut writer,
+                    error::ERR_UNSUPPORTED_SETUP,
+                    "resume is not supported",
+                )
+                .await;
+                return Ok(());
+            }
+            _ => None,
+        };
 
         // Create frame splitter.
-        let splitter = if mtu != 0 {
-            Some(Splitter::new(mtu))
+        let splitter = if options.mtu != 0 {
+            Some(Splitter::new(options.mtu))
         } else {
             None
         };
 
         // Init duplex socket.
-        let (snd_tx, mut snd_rx) = mpsc::unbounded_channel::<Frame>();
-        let mut socket = DuplexSocket::new(0, snd_tx, splitter).await;
-
-        // Begin loop for writing frames.
-        runtime::spawn(async move {
-            while let Some(frame) = snd_rx.recv().await {
-                if let Err(e) = writer.send(frame).await {
-                    error!("write frame failed: {}", e);
-                    break;
+        let (snd_tx, snd_rx) = mpsc::unbounded_channel::<Frame>();
+        let mut socket = DuplexSocket::new(2, snd_tx, splitter, options.limit_rate).await;
+        if let Some(strategy) = lease {
+            socket.set_lease_strategy(strategy);
+        }
+        if let Some(max) = options.max_streams {
+            socket.limit_streams(max);
+        }
+        if let Some(size) = options.max_reassembled_size {
+            socket.set_max_reassembled_size(size);
+        }
+        if let Some(recorder) = &layers.metrics {
+            socket.set_metrics(recorder.clone());
+        }
+        socket.set_span(span.clone());
+
+        let (read_tx, mut read_rx) = mpsc::unbounded_channel::<Frame>();
+        read_tx.send(first)?;
+
+        // Begin loop for pumping frames.
+        let (closing_tx, closing_rx) = oneshot::channel::<()>();
+        let driving = Self::drive(
+            writer, reader, snd_rx, read_tx, lifetime, attach, closing_rx,
+        );
+        runtime::spawn(trace::instrument(driving, &span));
+
+        let mut closing_tx = Some(closing_tx);
+        loop {
+            let frame = tokio::select! {
+                next = read_rx.recv() => match next {
+                    Some(it) => it,
+                    None => break,
+                },
+                deadline = shutdown_requested(&mut deadline), if closing_tx.is_some() => {
+                    socket.drain(deadline.saturating_duration_since(Instant::now()));
+                    let socket = socket.clone();
+                    let closing_tx = closing_tx.take();
+                    runtime::spawn(async move {
+                        Self::drain(socket, deadline).await;
+                        if let Some(tx) = closing_tx {
+                            let _ = tx.send(());
+                        }
+                    });
+                    continue;
+                }
+            };
+            if let Err(e) = socket.dispatch(frame, acceptor.as_ref().as_ref()).await {
+                match e.downcast_ref::<RSocketError>() {
+                    Some(RSocketError::ConnectionClosed(_)) => info!("connection closed: {}", e),
+                    _ => {
+                        error!("dispatch frame failed: {}", e);
+                        trace::error(&span, &e);
+                    }
                 }
+                break;
             }
-        });
+        }
 
-        let (read_tx, mut read_rx) = mpsc::unbounded_channel::<Frame>();
+        // the connection is over, fail the outstanding requests and abort the responders.
+        socket.close();
+        socket.terminate(|| RSocketError::ConnectionClosed("connection closed".into()));
 
-        runtime::spawn(async move {
-            loop {
-                match reader.next().await {
-                    Some(Ok(frame)) => {
-                        if let Err(e) = read_tx.send(frame) {
-                            error!("forward frame failed: {}", e);
+        if let (Some(token), Some(sessions)) = (token, sessions) {
+            sessions.inner.remove(&token);
+        }
+        Ok(())
+    }
+
+    /// Waits for the in-flight requests until the deadline, then closes the connection.
+    async fn drain(socket: DuplexSocket, deadline: Instant) {
+        socket.drained(deadline).await;
+        let errmsg = "server is shutting down";
+        socket.terminate(|| RSocketError::ConnectionClosed(errmsg.into()));
+        socket.send_error(error::ERR_CONN_CLOSED, errmsg);
+    }
+
+    /// Pumps frames of a session, and switches to new connections which resume it.
+    async fn drive(
+        mut writer: Box<FrameSink>,
+        mut reader: Box<FrameStream>,
+        mut outbound: mpsc::UnboundedReceiver<Frame>,
+        inbound: mpsc::UnboundedSender<Frame>,
+        lifetime: Option<Duration>,
+        attach: Option<(mpsc::UnboundedReceiver<Attach>, Duration)>,
+        mut closing: oneshot::Receiver<()>,
+    ) {
+        let (mut attach_rx, duration) = match attach {
+            Some(it) => it,
+            None => {
+                let res = tokio::select! {
+                    res = resume::pump(
+                        &mut writer,
+                        &mut reader,
+                        &mut outbound,
+                        &inbound,
+                        None,
+                        None,
+                        lifetime,
+                    ) => res,
+                    _ = &mut closing => Disconnect::Closed,
+                };
+                match res {
+                    Disconnect::Closed => resume::flush_and_close(&mut writer, &mut outbound).await,
+                    Disconnect::Expired => error!("close connection: keepalive timeout"),
+                    Disconnect::Dropped | Disconnect::Failed(_) => (),
+                }
+                return;
+            }
+        };
+        let mut cache = ResumeCache::default();
+        loop {
+            let next = tokio::select! {
+                res = resume::pump(
+                    &mut writer,
+                    &mut reader,
+                    &mut outbound,
+                    &inbound,
+                    Some(&mut cache),
+                    None,
+                    lifetime,
+                ) => Err(res),
+                next = attach_rx.recv() => Ok(next),
+                _ = &mut closing => {
+                    resume::flush_and_close(&mut writer, &mut outbound).await;
+                    break;
+                }
+            };
+            let attached = match next {
+                // the client resumes before the dropped connection has been noticed.
+                Ok(Some(it)) => it,
+                Ok(None) | Err(Disconnect::Closed) | Err(Disconnect::Failed(_)) => break,
+                Err(Disconnect::Dropped) | Err(Disconnect::Expired) => {
+                    let attached = tokio::select! {
+                        res = tokio::time::timeout(duration, attach_rx.recv()) => res,
+                        _ = &mut closing => break,
+                    };
+                    match attached {
+                        Ok(Some(it)) => it,
+                        _ => {
+                            info!("session has not been resumed in time");
                             break;
                         }
                     }
-                    Some(Err(e)) => {
-                        error!("read frame failed: {}", e);
-                        break;
-                    }
-                    None => {
-                        break;
-                    }
                 }
+            };
+            match Self::resume_session(attached, &mut cache).await {
+                Some((a, b)) => {
+                    writer = a;
+                    reader = b;
+                }
+                None => break,
             }
-        });
+        }
+    }
 
-        while let Some(frame) = read_rx.recv().await {
-            if let Err(e) = socket.dispatch(frame, acceptor.as_ref().as_ref()).await {
-                error!("dispatch frame failed: {}", e);
-                break;
+    async fn resume_session(
+        attached: Attach,
+        cache: &mut ResumeCache,
+    ) -> Option<(Box<FrameSink>, Box<FrameStream>)> {
+        let (mut writer, reader, resume) = attached;
+        // the client must still hold all the frames which have not been received.
+        let frames = if resume.get_first_available_client_position() <= cache.received_position() {
+            cache.replay(resume.get_last_received_server_position())
+        } else {
+            None
+        };
+        let frames = match frames {
+            Some(it) => it,
+            None => {
+                Self::reject(&mut writer, error::ERR_REJECT_RESUME, "invalid positions").await;
+                return None;
+            }
+        };
+        let sending = frame::ResumeOK::builder(0, 0)
+            .set_position(cache.received_position())
+            .build();
+        if let Err(e) = writer.send(sending).await {
+            error!("write frame failed: {}", e);
+            return None;
+        }
+        for next in frames {
+            if let Err(e) = writer.send(next).await {
+                error!("write frame failed: {}", e);
+                return None;
             }
         }
+        info!("session has been resumed");
+        Some((writer, reader))
+    }
 
-        Ok(())
+    async fn on_resume(
+        sessions: Option<Sessions>,
+        writer: Box<FrameSink>,
+        reader: Box<FrameStream>,
+        resume: frame::Resume,
+    ) {
+        let attach = match (&sessions, resume.get_token()) {
+            (Some(sessions), Some(token)) => sessions.inner.get(token).map(|it| it.clone()),
+            _ => None,
+        };
+        let mut writer = match attach {
+            Some(tx) => match tx.send((writer, reader, resume)) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError((w, _, _))) => w,
+            },
+            None => writer,
+        };
+        Self::reject(&mut writer, error::ERR_REJECT_RESUME, "no such session").await;
+    }
+
+    async fn reject(writer: &mut Box<FrameSink>, code: u32, errmsg: &str) {
+        let sending = frame::Error::builder(0, 0)
+            .set_code(code)
+            .set_data(Bytes::from(errmsg.to_owned()))
+            .build();
+        if let Err(e) = writer.send(sending).await {
+            error!("write frame failed: {}", e);
+        }
+    }
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        let (deadline, _) = watch::channel(None);
+        let (running, _) = watch::channel(0);
+        ShutdownHandle {
+            deadline: Arc::new(deadline),
+            running: Arc::new(running),
+        }
+    }
+
+    /// Begins the graceful shutdown, in-flight requests are cancelled after `timeout`. Resolves
+    /// once `serve` has returned, or at once if no server is serving with the handle.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.deadline.send_replace(Some(Instant::now() + timeout));
+        let mut running = self.running.subscribe();
+        let _ = running.wait_for(|n| *n == 0).await;
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves with the deadline once the shutdown begins, never resolves without a handle.
+async fn shutdown_requested(deadline: &mut Option<Deadline>) -> Instant {
+    if let Some(rx) = deadline {
+        if let Ok(it) = rx.wait_for(|it| it.is_some()).await {
+            if let Some(deadline) = *it {
+                return deadline;
+            }
+        }
     }
+    futures::future::pending().await
 }
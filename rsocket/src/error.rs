@@ -1,6 +1,8 @@
 use std::fmt;
 use std::io;
+use std::time::Duration;
 
+use bytes::Bytes;
 use thiserror::Error;
 
 pub const ERR_INVALID_SETUP: u32 = 0x0000_0001;
@@ -14,6 +16,10 @@ pub const ERR_REJECTED: u32 = 0x0000_0202;
 pub const ERR_CANCELED: u32 = 0x0000_0203;
 pub const ERR_INVALID: u32 = 0x0000_0204;
 
+/// The range of error codes reserved for application layer errors, see [`RSocketError::Custom`].
+pub const ERR_CUSTOM_MIN: u32 = 0x0000_0301;
+pub const ERR_CUSTOM_MAX: u32 = 0xFFFF_FFFE;
+
 #[derive(Error, Debug)]
 pub enum RSocketError {
     // Protocol errors:
@@ -39,11 +45,26 @@ pub enum RSocketError {
     RequestInvalid(String),
     #[error("RESERVED({0}): {1}")]
     Reserved(u32, String),
+    #[error("ERROR({0:#x}) with {} bytes of non-UTF8 data", .1.len())]
+    BinaryError(u32, Bytes),
+    /// An application layer error with a code in `ERR_CUSTOM_MIN..=ERR_CUSTOM_MAX` and raw data.
+    /// Responders may return it to send the code as it is, other errors are sent as
+    /// APPLICATION_ERROR.
+    #[error("CUSTOM({0:#x}): {}", String::from_utf8_lossy(.1))]
+    Custom(u32, Bytes),
 
     // Codec errors:
     #[error("this frame is incomplete")]
     InCompleteFrame,
+    #[error("unknown frame type {0}")]
+    UnknownFrameType(u16),
+    #[error("ignored frame type {0}")]
+    IgnoredFrame(u16),
     // Custom errors:
+    #[error("stream ids are exhausted")]
+    StreamIdExhausted,
+    #[error("request timed out after {0:?}")]
+    RequestTimeout(Duration),
     #[error("{0}")]
     WithDescription(String),
     #[error(transparent)]
@@ -68,4 +89,78 @@ impl RSocketError {
             _ => RSocketError::Reserved(code, desc),
         }
     }
+
+    /// Creates an application layer error, the code should be in `ERR_CUSTOM_MIN..=ERR_CUSTOM_MAX`.
+    pub fn custom<D>(code: u32, data: D) -> Self
+    where
+        D: Into<Bytes>,
+    {
+        RSocketError::Custom(code, data.into())
+    }
+
+    /// Returns the error code of an error received from or sent to the peer.
+    pub fn code(&self) -> Option<u32> {
+        let code = match self {
+            RSocketError::InvalidSetup(_) => ERR_INVALID_SETUP,
+            RSocketError::UnsupportedSetup(_) => ERR_UNSUPPORTED_SETUP,
+            RSocketError::RejectedSetup(_) => ERR_REJECT_SETUP,
+            RSocketError::RejectedResume(_) => ERR_REJECT_RESUME,
+            RSocketError::ConnectionException(_) => ERR_CONN_FAILED,
+            RSocketError::ConnectionClosed(_) => ERR_CONN_CLOSED,
+            RSocketError::ApplicationException(_) => ERR_APPLICATION,
+            RSocketError::RequestRejected(_) => ERR_REJECTED,
+            RSocketError::RequestCancelled(_) => ERR_CANCELED,
+            RSocketError::RequestInvalid(_) => ERR_INVALID,
+            RSocketError::Reserved(code, _)
+            | RSocketError::BinaryError(code, _)
+            | RSocketError::Custom(code, _) => *code,
+            _ => return None,
+        };
+        Some(code)
+    }
+
+    /// Returns the data of an error received from or sent to the peer.
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            RSocketError::InvalidSetup(s)
+            | RSocketError::UnsupportedSetup(s)
+            | RSocketError::RejectedSetup(s)
+            | RSocketError::RejectedResume(s)
+            | RSocketError::ConnectionException(s)
+            | RSocketError::ConnectionClosed(s)
+            | RSocketError::ApplicationException(s)
+            | RSocketError::RequestRejected(s)
+            | RSocketError::RequestCancelled(s)
+            | RSocketError::RequestInvalid(s)
+            | RSocketError::Reserved(_, s) => Some(s.as_bytes()),
+            RSocketError::BinaryError(_, b) | RSocketError::Custom(_, b) => Some(&b[..]),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_custom_code(code: u32) -> bool {
+        (ERR_CUSTOM_MIN..=ERR_CUSTOM_MAX).contains(&code)
+    }
+
+    /// Maps the code and data of an ERROR frame, data which is not UTF8 is kept as it is.
+    pub(crate) fn from_error_frame(code: u32, data: Option<&Bytes>) -> Self {
+        if Self::is_custom_code(code) {
+            return RSocketError::Custom(code, data.cloned().unwrap_or_default());
+        }
+        match data {
+            None => Self::must_new_from_code(code, String::new()),
+            Some(b) => match std::str::from_utf8(b) {
+                Ok(desc) => Self::must_new_from_code(code, desc.to_owned()),
+                Err(_) => RSocketError::BinaryError(code, b.clone()),
+            },
+        }
+    }
+
+    /// Whether a frame could not be decoded, which is a protocol violation of the peer.
+    pub(crate) fn is_malformed_frame(&self) -> bool {
+        matches!(
+            self,
+            RSocketError::InCompleteFrame | RSocketError::UnknownFrameType(_)
+        )
+    }
 }
@@ -40,7 +40,7 @@ pub const REQUEST_MAX: u32 = 0x7FFF_FFFF; // 2147483647
 
 pub(crate) const LEN_HEADER: usize = 6;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Body {
     Setup(Setup),
     Lease(Lease),
@@ -58,7 +58,7 @@ pub enum Body {
     ResumeOK(ResumeOK),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Frame {
     pub(crate) stream_id: u32,
     pub(crate) body: Body,
@@ -168,7 +168,9 @@ impl Frame {
             Self::TYPE_ERROR => Error::decode(flag, b).map(Body::Error),
             Self::TYPE_RESUME_OK => ResumeOK::decode(flag, b).map(Body::ResumeOK),
             Self::TYPE_RESUME => Resume::decode(flag, b).map(Body::Resume),
-            typ => unreachable!("invalid frame type {}!", typ),
+            // unknown frames are either skipped or treated as a connection error.
+            typ if flag & Self::FLAG_IGNORE != 0 => Err(RSocketError::IgnoredFrame(typ).into()),
+            typ => Err(RSocketError::UnknownFrameType(typ).into()),
         };
         body.map(|it| Frame::new(sid, it, flag))
     }
@@ -3,7 +3,7 @@ use bytes::{BufMut, Bytes, BytesMut};
 use super::{utils, Body, Frame};
 use crate::utils::Writeable;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RequestFNF {
     metadata: Option<Bytes>,
     data: Option<Bytes>,
@@ -4,7 +4,7 @@ use super::{utils, Body, Frame, REQUEST_MAX};
 use crate::error::RSocketError;
 use crate::utils::Writeable;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RequestStream {
     initial_request_n: u32,
     metadata: Option<Bytes>,
@@ -4,7 +4,7 @@ use super::{Body, Frame, Version};
 use crate::error::RSocketError;
 use crate::utils::Writeable;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Resume {
     version: Version,
     token: Option<Bytes>,
@@ -113,9 +113,12 @@ impl ResumeBuilder {
 impl Writeable for Resume {
     fn write_to(&self, bf: &mut BytesMut) {
         self.version.write_to(bf);
-        if let Some(b) = self.get_token() {
-            bf.put_u16(b.len() as u16);
-            bf.extend_from_slice(b);
+        match self.get_token() {
+            Some(b) => {
+                bf.put_u16(b.len() as u16);
+                bf.extend_from_slice(b);
+            }
+            None => bf.put_u16(0),
         }
         bf.put_u64(self.get_last_received_server_position());
         bf.put_u64(self.get_first_available_client_position());
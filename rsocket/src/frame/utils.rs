@@ -13,8 +13,11 @@ pub(crate) fn read_payload(
         if bf.len() < 3 {
             return Err(RSocketError::InCompleteFrame.into());
         }
-        let n = u24::read_advance(bf);
-        Some(bf.split_to(n.into()).freeze())
+        let n: usize = u24::read_advance(bf).into();
+        if bf.len() < n {
+            return Err(RSocketError::InCompleteFrame.into());
+        }
+        Some(bf.split_to(n).freeze())
     } else {
         None
     };
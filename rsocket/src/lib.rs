@@ -112,14 +112,16 @@ pub mod frame;
 mod frame;
 
 mod core;
+pub mod metrics;
 mod payload;
 pub mod prelude;
 pub mod runtime;
 mod spi;
+mod trace;
 pub mod transport;
 pub mod utils;
 
 pub type Error = Box<dyn std::error::Error + Sync + Send>;
 pub type Result<T> = anyhow::Result<T>;
 
-pub use crate::core::{Client, ClientBuilder, ServerBuilder};
+pub use crate::core::{Client, ClientBuilder, ServerBuilder, ShutdownHandle};
@@ -0,0 +1,298 @@
+//! Metrics of clients and servers.
+//!
+//! A [`MetricsRecorder`] installed by `ClientBuilder::metrics` or `ServerBuilder::metrics` is
+//! notified of connections, frames, requests, streams and errors, so any metrics backend can be
+//! plugged in. [`InMemoryMetrics`] keeps everything in memory and can be read at any time.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{future, SinkExt, StreamExt};
+
+use crate::error::RSocketError;
+use crate::frame::{Body, Frame};
+use crate::transport::{Direction, FrameSink, FrameStream};
+use crate::utils::Writeable;
+
+/// The interaction model of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interaction {
+    FireAndForget,
+    RequestResponse,
+    RequestStream,
+    RequestChannel,
+    MetadataPush,
+}
+
+/// The side of an interaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    /// The request has been sent to the peer.
+    Requester,
+    /// The request has been received from the peer.
+    Responder,
+}
+
+/// Receives the metrics of a client or server, every method does nothing by default.
+///
+/// A recorder is shared by all the connections of a server, methods may be called concurrently.
+pub trait MetricsRecorder: Send + Sync {
+    /// A connection has been established, including the connections which resume a session.
+    fn connection_opened(&self) {}
+
+    /// A connection has been closed.
+    fn connection_closed(&self) {}
+
+    /// A frame has been sent or received, the size excludes the framing of the transport.
+    fn frame(&self, direction: Direction, size: usize) {}
+
+    /// An ERROR frame has been sent or received.
+    fn error(&self, direction: Direction, code: u32) {}
+
+    /// A request has been sent or received.
+    fn request(&self, side: Side, interaction: Interaction) {}
+
+    /// A request_response, request_stream or request_channel has started.
+    fn stream_opened(&self, side: Side, interaction: Interaction) {}
+
+    /// A stream started by `stream_opened` has terminated.
+    fn stream_closed(&self, side: Side, interaction: Interaction) {}
+
+    /// A request_response sent to the peer has received its response or error.
+    fn request_response_latency(&self, elapsed: Duration) {}
+}
+
+/// The upper bounds of the buckets of latency histograms, in milliseconds.
+const LATENCY_BOUNDS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// A histogram of durations with fixed buckets.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BOUNDS.len() + 1],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let i = LATENCY_BOUNDS
+            .iter()
+            .position(|it| value <= Duration::from_millis(*it))
+            .unwrap_or(LATENCY_BOUNDS.len());
+        self.counts[i] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// Returns the number of observed values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of observed values.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the inclusive upper bound and the count of each bucket, the bound of the last
+    /// bucket is `Duration::MAX`.
+    pub fn buckets(&self) -> Vec<(Duration, u64)> {
+        LATENCY_BOUNDS
+            .iter()
+            .map(|it| Duration::from_millis(*it))
+            .chain(std::iter::once(Duration::MAX))
+            .zip(self.counts.iter().copied())
+            .collect()
+    }
+}
+
+/// A recorder which keeps all the metrics in memory.
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    connections: AtomicI64,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    requests: Mutex<HashMap<(Side, Interaction), u64>>,
+    streams: Mutex<HashMap<(Side, Interaction), i64>>,
+    errors: Mutex<HashMap<(Direction, u32), u64>>,
+    latency: Mutex<Histogram>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> InMemoryMetrics {
+        InMemoryMetrics::default()
+    }
+
+    /// Returns the number of open connections.
+    pub fn open_connections(&self) -> i64 {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of frames sent or received.
+    pub fn frames(&self, direction: Direction) -> u64 {
+        match direction {
+            Direction::Inbound => self.frames_in.load(Ordering::SeqCst),
+            Direction::Outbound => self.frames_out.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the number of bytes sent or received.
+    pub fn bytes(&self, direction: Direction) -> u64 {
+        match direction {
+            Direction::Inbound => self.bytes_in.load(Ordering::SeqCst),
+            Direction::Outbound => self.bytes_out.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the number of requests sent or received.
+    pub fn requests(&self, side: Side, interaction: Interaction) -> u64 {
+        get(&self.requests, &(side, interaction))
+    }
+
+    /// Returns the number of streams which are still in-flight.
+    pub fn active_streams(&self, side: Side, interaction: Interaction) -> i64 {
+        get(&self.streams, &(side, interaction))
+    }
+
+    /// Returns the number of ERROR frames with the code sent or received.
+    pub fn errors(&self, direction: Direction, code: u32) -> u64 {
+        get(&self.errors, &(direction, code))
+    }
+
+    /// Returns the latency of request_response sent to the peer.
+    pub fn request_response_latency(&self) -> Histogram {
+        self.latency.lock().unwrap().clone()
+    }
+}
+
+impl MetricsRecorder for InMemoryMetrics {
+    fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn frame(&self, direction: Direction, size: usize) {
+        let (frames, bytes) = match direction {
+            Direction::Inbound => (&self.frames_in, &self.bytes_in),
+            Direction::Outbound => (&self.frames_out, &self.bytes_out),
+        };
+        frames.fetch_add(1, Ordering::SeqCst);
+        bytes.fetch_add(size as u64, Ordering::SeqCst);
+    }
+
+    fn error(&self, direction: Direction, code: u32) {
+        add(&self.errors, (direction, code), 1);
+    }
+
+    fn request(&self, side: Side, interaction: Interaction) {
+        add(&self.requests, (side, interaction), 1);
+    }
+
+    fn stream_opened(&self, side: Side, interaction: Interaction) {
+        add(&self.streams, (side, interaction), 1);
+    }
+
+    fn stream_closed(&self, side: Side, interaction: Interaction) {
+        add(&self.streams, (side, interaction), -1);
+    }
+
+    fn request_response_latency(&self, elapsed: Duration) {
+        self.latency.lock().unwrap().observe(elapsed);
+    }
+}
+
+fn get<K, V>(counters: &Mutex<HashMap<K, V>>, key: &K) -> V
+where
+    K: Eq + Hash,
+    V: Copy + Default,
+{
+    counters
+        .lock()
+        .unwrap()
+        .get(key)
+        .copied()
+        .unwrap_or_default()
+}
+
+fn add<K, V>(counters: &Mutex<HashMap<K, V>>, key: K, delta: V)
+where
+    K: Eq + Hash,
+    V: std::ops::AddAssign + Default,
+{
+    *counters.lock().unwrap().entry(key).or_default() += delta;
+}
+
+/// Held by a stream until it terminates, see `MetricsRecorder::stream_opened`.
+pub(crate) struct ActiveStream {
+    recorder: Arc<dyn MetricsRecorder>,
+    side: Side,
+    interaction: Interaction,
+}
+
+impl ActiveStream {
+    /// Records the request, and opens the stream.
+    pub(crate) fn open(
+        recorder: Arc<dyn MetricsRecorder>,
+        side: Side,
+        interaction: Interaction,
+    ) -> ActiveStream {
+        recorder.request(side, interaction);
+        recorder.stream_opened(side, interaction);
+        ActiveStream {
+            recorder,
+            side,
+            interaction,
+        }
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.recorder.stream_closed(self.side, self.interaction);
+    }
+}
+
+/// Held by the inbound of a connection, the connection is closed once it is dropped.
+struct OpenConnection(Arc<dyn MetricsRecorder>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.connection_closed();
+    }
+}
+
+/// Records the frames of a connection, and keeps it open until the inbound is dropped.
+pub(crate) fn meter_frames(
+    recorder: Arc<dyn MetricsRecorder>,
+    sink: Box<FrameSink>,
+    stream: Box<FrameStream>,
+) -> (Box<FrameSink>, Box<FrameStream>) {
+    recorder.connection_opened();
+    let outbound = recorder.clone();
+    let sink = sink.with(move |frame: Frame| {
+        record_frame(outbound.as_ref(), Direction::Outbound, &frame);
+        future::ready(Ok::<_, RSocketError>(frame))
+    });
+    let connection = OpenConnection(recorder);
+    let stream = stream.inspect(move |next| {
+        if let Ok(frame) = next {
+            record_frame(connection.0.as_ref(), Direction::Inbound, frame);
+        }
+    });
+    (Box::new(sink), Box::new(stream))
+}
+
+fn record_frame(recorder: &dyn MetricsRecorder, direction: Direction, frame: &Frame) {
+    recorder.frame(direction, frame.len());
+    if let Body::Error(e) = frame.get_body_ref() {
+        recorder.error(direction, e.get_code());
+    }
+}
@@ -3,7 +3,7 @@ use std::time::Duration;
 use bytes::Bytes;
 
 use super::misc::bytes_to_utf8;
-use crate::frame::Setup;
+use crate::frame::{Setup, Version};
 use crate::utils::DEFAULT_MIME_TYPE;
 
 #[derive(Debug)]
@@ -13,6 +13,9 @@ pub struct SetupPayload {
     keepalive: (Duration, Duration),
     mime_m: Option<Bytes>,
     mime_d: Option<Bytes>,
+    token: Option<Bytes>,
+    version: Version,
+    flags: u16,
 }
 
 #[derive(Debug)]
@@ -35,6 +38,9 @@ impl SetupPayloadBuilder {
                 keepalive: (Duration::from_secs(20), Duration::from_secs(90)),
                 mime_m: Some(Bytes::from(DEFAULT_MIME_TYPE.to_owned())),
                 mime_d: Some(Bytes::from(DEFAULT_MIME_TYPE.to_owned())),
+                token: None,
+                version: Version::default(),
+                flags: 0,
             },
         }
     }
@@ -95,6 +101,11 @@ impl SetupPayloadBuilder {
         self
     }
 
+    pub(crate) fn set_resume_token(mut self, token: Option<Bytes>) -> Self {
+        self.inner.token = token;
+        self
+    }
+
     pub fn build(self) -> SetupPayload {
         self.inner
     }
@@ -128,24 +139,43 @@ impl SetupPayload {
     pub fn data_mime_type(&self) -> Option<&str> {
         bytes_to_utf8(&self.mime_d)
     }
+
+    pub fn resume_token(&self) -> Option<&Bytes> {
+        self.token.as_ref()
+    }
+
+    /// Returns the protocol version of the SETUP frame.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the flags of the SETUP frame, e.g. `Frame::FLAG_LEASE`.
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub(crate) fn set_flags(&mut self, flags: u16) {
+        self.flags = flags;
+    }
 }
 
 impl From<Setup> for SetupPayload {
     fn from(input: Setup) -> SetupPayload {
         let mut bu = SetupPayload::builder();
-        // TODO: fill other properties.
-        if let Some(m) = input.get_mime_data() {
-            bu = bu.set_data_mime_type(m);
-        }
-        if let Some(m) = input.get_mime_metadata() {
-            bu = bu.set_metadata_mime_type(m);
-        }
+        // invalid MIME types are left empty, so they can be rejected.
+        bu.inner.mime_d = input.get_mime_data().map(|it| Bytes::from(it.to_owned()));
+        bu.inner.mime_m = input
+            .get_mime_metadata()
+            .map(|it| Bytes::from(it.to_owned()));
         let keepalive = (input.get_keepalive(), input.get_lifetime());
+        let version = input.get_version();
+        bu = bu.set_resume_token(input.get_token().cloned());
         let (d, m) = input.split();
         bu.inner.d = d;
         bu.inner.m = m;
         let mut pa = bu.build();
         pa.keepalive = keepalive;
+        pa.version = version;
         pa
     }
 }
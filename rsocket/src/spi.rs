@@ -1,9 +1,12 @@
 use std::future::Future;
 use std::pin::Pin;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::Stream;
 
+use crate::frame::REQUEST_MAX;
 use crate::payload::{Payload, SetupPayload};
 use crate::Result;
 
@@ -11,8 +14,111 @@ pub type ClientResponder = Box<dyn Send + Sync + Fn() -> Box<dyn RSocket>>;
 pub type ServerResponder =
     Box<dyn Send + Sync + Fn(SetupPayload, Box<dyn RSocket>) -> Result<Box<dyn RSocket>>>;
 
+/// Wraps an RSocket, e.g. to add auth, logging or metrics around the requests of every
+/// connection.
+pub type RSocketInterceptor = Box<dyn Send + Sync + Fn(Box<dyn RSocket>) -> Box<dyn RSocket>>;
+
 pub type Flux<T> = Pin<Box<dyn Send + Stream<Item = T>>>;
 
+/// Produces the leases which a server grants to each connection which requires leasing.
+pub type LeaseStrategy = Box<dyn Send + Sync + Fn() -> Flux<Lease>>;
+
+/// Replenishment policy of REQUEST_N for the inbound side of a stream or channel.
+///
+/// The requester asks for `high_tide` payloads at first, and tops the demand up to
+/// `high_tide` again once the outstanding demand drops to `low_tide`.
+#[derive(Debug, Clone, Copy)]
+pub struct LimitRate {
+    high_tide: u32,
+    low_tide: u32,
+}
+
+impl LimitRate {
+    pub fn new(high_tide: u32, low_tide: u32) -> LimitRate {
+        assert!(high_tide > 0, "high tide must be positive!");
+        assert!(
+            low_tide < high_tide,
+            "low tide must be less than high tide!"
+        );
+        LimitRate {
+            high_tide: high_tide.min(REQUEST_MAX),
+            low_tide,
+        }
+    }
+
+    pub fn unbounded() -> LimitRate {
+        LimitRate {
+            high_tide: REQUEST_MAX,
+            low_tide: 0,
+        }
+    }
+
+    pub fn high_tide(&self) -> u32 {
+        self.high_tide
+    }
+
+    pub fn low_tide(&self) -> u32 {
+        self.low_tide
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.high_tide == REQUEST_MAX
+    }
+}
+
+impl Default for LimitRate {
+    fn default() -> LimitRate {
+        LimitRate::unbounded()
+    }
+}
+
+/// What a requester does once its concurrent requests reach the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOverflow {
+    /// Waits until an in-flight request terminates.
+    Queue,
+    /// Fails the request with `RSocketError::RequestRejected` at once.
+    Reject,
+}
+
+/// A lease which allows the peer to send `number_of_requests` requests within `ttl`.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    ttl: Duration,
+    number_of_requests: u32,
+    metadata: Option<Bytes>,
+}
+
+impl Lease {
+    pub fn new(ttl: Duration, number_of_requests: u32) -> Lease {
+        Lease {
+            ttl,
+            number_of_requests,
+            metadata: None,
+        }
+    }
+
+    pub fn set_metadata<A>(mut self, metadata: A) -> Self
+    where
+        A: Into<Vec<u8>>,
+    {
+        self.metadata = Some(Bytes::from(metadata.into()));
+        self
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn number_of_requests(&self) -> u32 {
+        self.number_of_requests
+    }
+
+    pub fn metadata(&self) -> Option<&Bytes> {
+        self.metadata.as_ref()
+    }
+}
+
 /// A contract providing different interaction models for RSocket protocol.
 ///
 /// RSocket trait is based on `async_trait` crate.
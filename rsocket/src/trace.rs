@@ -0,0 +1,74 @@
+//! Optional integration with `tracing`: every connection and every stream gets its own span, and
+//! errors are recorded as events. Everything is a no-op unless the `tracing` feature is enabled.
+
+use std::fmt::Display;
+use std::future::Future;
+
+use crate::metrics::{Interaction, Side};
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn none() -> Span {
+        Span
+    }
+}
+
+/// Creates the root span of a connection, `side` is either "client" or "server".
+#[cfg(feature = "tracing")]
+pub(crate) fn connection(side: &'static str, peer: Option<String>) -> Span {
+    tracing::info_span!(
+        parent: None,
+        "rsocket.connection",
+        side,
+        peer = peer.as_deref().unwrap_or("unknown")
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn connection(side: &'static str, peer: Option<String>) -> Span {
+    Span
+}
+
+/// Creates the span of a stream within the span of its connection.
+#[cfg(feature = "tracing")]
+pub(crate) fn stream(connection: &Span, sid: u32, side: Side, interaction: Interaction) -> Span {
+    tracing::info_span!(
+        parent: connection,
+        "rsocket.stream",
+        stream_id = sid,
+        side = ?side,
+        interaction = ?interaction
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn stream(connection: &Span, sid: u32, side: Side, interaction: Interaction) -> Span {
+    Span
+}
+
+/// Runs the future within the span.
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(fut: F, span: &Span) -> impl Future<Output = F::Output> {
+    tracing::Instrument::instrument(fut, span.clone())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(fut: F, span: &Span) -> impl Future<Output = F::Output> {
+    fut
+}
+
+/// Records an error as an event of the span.
+#[cfg(feature = "tracing")]
+pub(crate) fn error(span: &Span, e: &dyn Display) {
+    tracing::error!(parent: span, error = %e, "rsocket error");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn error(span: &Span, e: &dyn Display) {}
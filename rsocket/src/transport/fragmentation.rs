@@ -7,8 +7,12 @@ use crate::payload::Payload;
 
 pub(crate) const MIN_MTU: usize = 64;
 
+/// The default limit of a reassembled payload, data and metadata included.
+pub(crate) const DEFAULT_MAX_REASSEMBLED_SIZE: usize = 16 * 1024 * 1024;
+
 pub(crate) struct Joiner {
     inner: LinkedList<Frame>,
+    size: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +35,29 @@ impl Splitter {
             meta,
         }
     }
+
+    /// ERROR frames can't be fragmented, so their description is truncated to fit in the MTU.
+    pub(crate) fn fit_error<'a>(&self, desc: &'a str) -> &'a str {
+        let mut end = self.error_capacity();
+        if desc.len() <= end {
+            return desc;
+        }
+        while !desc.is_char_boundary(end) {
+            end -= 1;
+        }
+        &desc[..end]
+    }
+
+    /// Same as `fit_error`, but for raw error data.
+    pub(crate) fn fit_error_data(&self, data: &Bytes) -> Bytes {
+        data.slice(..data.len().min(self.error_capacity()))
+    }
+
+    #[inline]
+    fn error_capacity(&self) -> usize {
+        // skip 4 bytes. (error code is u32)
+        self.mtu - frame::LEN_HEADER - 4
+    }
 }
 
 struct SplitterIter {
@@ -51,6 +78,8 @@ impl Iterator for SplitterIter {
         let mut d: Option<Bytes> = None;
         let mut left = self.mtu - frame::LEN_HEADER - self.skip;
         if let Some(it) = &mut self.meta {
+            // the length of metadata is u24.
+            left -= 3;
             let msize = it.len();
             if left < msize {
                 m = Some(it.split_to(left));
@@ -115,6 +144,55 @@ impl Joiner {
     pub(crate) fn new() -> Joiner {
         Joiner {
             inner: LinkedList::new(),
+            size: 0,
+        }
+    }
+
+    /// The size of the payload received so far.
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reassembles the fragments into the frame type of the first one.
+    pub(crate) fn join(self) -> Frame {
+        let sid = self.get_stream_id();
+        let flag = self.get_flag();
+        match &self.first().body {
+            Body::RequestResponse(_) => {
+                let pa: Payload = self.into();
+                frame::RequestResponse::builder(sid, flag)
+                    .set_all(pa.split())
+                    .build()
+            }
+            Body::RequestStream(b) => {
+                let n = b.get_initial_request_n();
+                let pa: Payload = self.into();
+                frame::RequestStream::builder(sid, flag)
+                    .set_initial_request_n(n)
+                    .set_all(pa.split())
+                    .build()
+            }
+            Body::RequestFNF(_) => {
+                let pa: Payload = self.into();
+                frame::RequestFNF::builder(sid, flag)
+                    .set_all(pa.split())
+                    .build()
+            }
+            Body::RequestChannel(b) => {
+                let n = b.get_initial_request_n();
+                let pa: Payload = self.into();
+                frame::RequestChannel::builder(sid, flag)
+                    .set_initial_request_n(n)
+                    .set_all(pa.split())
+                    .build()
+            }
+            // only followable frames are pushed, the rest are payloads.
+            _ => {
+                let pa: Payload = self.into();
+                frame::Payload::builder(sid, flag)
+                    .set_all(pa.split())
+                    .build()
+            }
         }
     }
 
@@ -122,8 +200,14 @@ impl Joiner {
         self.first().get_stream_id()
     }
 
+    /// The flags of the first fragment, plus NEXT/COMPLETE which a peer may set on the last one.
     pub(crate) fn get_flag(&self) -> u16 {
-        self.first().get_flag() & !Frame::FLAG_FOLLOW
+        let last = self
+            .inner
+            .back()
+            .map(|it| it.get_flag() & (Frame::FLAG_NEXT | Frame::FLAG_COMPLETE))
+            .unwrap_or(0);
+        (self.first().get_flag() | last) & !Frame::FLAG_FOLLOW
     }
 
     pub(crate) fn first(&self) -> &Frame {
@@ -131,6 +215,15 @@ impl Joiner {
     }
 
     pub(crate) fn push(&mut self, next: Frame) {
+        let (d, m) = match &next.body {
+            Body::RequestResponse(body) => (body.get_data(), body.get_metadata()),
+            Body::RequestStream(body) => (body.get_data(), body.get_metadata()),
+            Body::RequestChannel(body) => (body.get_data(), body.get_metadata()),
+            Body::RequestFNF(body) => (body.get_data(), body.get_metadata()),
+            Body::Payload(body) => (body.get_data(), body.get_metadata()),
+            _ => (None, None),
+        };
+        self.size += d.map(|it| it.len()).unwrap_or(0) + m.map(|it| it.len()).unwrap_or(0);
         self.inner.push_back(next);
     }
 }
@@ -142,7 +235,7 @@ mod tests {
 
     use crate::frame::{self, Frame};
     use crate::payload::Payload;
-    use crate::transport::{Joiner, Splitter};
+    use crate::transport::{Joiner, Splitter, MIN_MTU};
 
     #[test]
     fn test_joiner() {
@@ -161,10 +254,95 @@ mod tests {
                 .build();
             joiner.push(next);
         }
+        assert_eq!(12 + 10 * 20, joiner.size());
         let pa: Payload = joiner.into();
         println!("payload: {:?}", pa);
     }
 
+    #[test]
+    fn test_joiner_keeps_request_type() {
+        let mut joiner = Joiner::new();
+        joiner.push(
+            frame::RequestStream::builder(1, Frame::FLAG_FOLLOW)
+                .set_initial_request_n(8)
+                .set_data(Bytes::from("hello"))
+                .build(),
+        );
+        joiner.push(
+            frame::Payload::builder(1, 0)
+                .set_data(Bytes::from("world"))
+                .build(),
+        );
+        let joined = joiner.join();
+        assert_eq!(0, joined.get_flag() & Frame::FLAG_FOLLOW);
+        match joined.get_body() {
+            frame::Body::RequestStream(b) => {
+                assert_eq!(8, b.get_initial_request_n());
+                assert_eq!(Some(&Bytes::from("helloworld")), b.get_data());
+            }
+            body => panic!("should be REQUEST_STREAM: {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_joiner_takes_complete_of_last() {
+        let mut joiner = Joiner::new();
+        joiner.push(
+            frame::Payload::builder(1, Frame::FLAG_FOLLOW | Frame::FLAG_NEXT)
+                .set_data(Bytes::from("hello"))
+                .build(),
+        );
+        joiner.push(
+            frame::Payload::builder(1, Frame::FLAG_FOLLOW)
+                .set_data(Bytes::from(" "))
+                .build(),
+        );
+        joiner.push(
+            frame::Payload::builder(1, Frame::FLAG_COMPLETE)
+                .set_data(Bytes::from("world"))
+                .build(),
+        );
+        let joined = joiner.join();
+        assert_eq!(0, joined.get_flag() & Frame::FLAG_FOLLOW);
+        assert!(joined.has_next());
+        assert!(joined.has_complete());
+        match joined.get_body() {
+            frame::Body::Payload(b) => {
+                assert_eq!(Some(&Bytes::from("hello world")), b.get_data());
+            }
+            body => panic!("should be PAYLOAD: {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_splitter_respects_mtu() {
+        let input = Payload::builder()
+            .set_data(Bytes::from(vec![b'd'; 300]))
+            .set_metadata(Bytes::from(vec![b'm'; 100]))
+            .build();
+        let sp = Splitter::new(MIN_MTU);
+        let mut total = 0;
+        for (i, it) in sp.cut(input, 4).enumerate() {
+            let skip = if i == 0 { 4 } else { 0 };
+            let metadata_len = it.metadata().map(|m| m.len()).unwrap_or(0);
+            let data_len = it.data().map(|d| d.len()).unwrap_or(0);
+            let prefix = if it.metadata().is_some() { 3 } else { 0 };
+            let len = frame::LEN_HEADER + skip + prefix + metadata_len + data_len;
+            assert!(len <= MIN_MTU, "fragment #{} is {} bytes", i, len);
+            total += metadata_len + data_len;
+        }
+        assert_eq!(400, total);
+
+        let desc = "é".repeat(MIN_MTU);
+        let fit = sp.fit_error(&desc);
+        assert!(fit.len() <= MIN_MTU - frame::LEN_HEADER - 4);
+        assert!(desc.starts_with(fit));
+
+        let data = Bytes::from(vec![0u8; MIN_MTU]);
+        let fit = sp.fit_error_data(&data);
+        assert_eq!(MIN_MTU - frame::LEN_HEADER - 4, fit.len());
+    }
+
     #[test]
     fn test_splitter() {
         let input = Payload::builder()
@@ -1,22 +1,65 @@
-use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::frame::Frame;
+use dashmap::DashMap;
+use futures::{future, stream, SinkExt, StreamExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 
+use super::spi::{Direction, FrameInterceptor, FrameSink, FrameStream};
+use crate::error::RSocketError;
+use crate::frame::{Frame, REQUEST_MAX};
+use crate::metrics::{self, MetricsRecorder};
+use crate::spi::RequestOverflow;
+use crate::Result;
+
+/// The largest stream id, stream ids are 31-bit unsigned integers.
+pub(crate) const MAX_STREAM_ID: u32 = 0x7FFF_FFFF;
+
+/// Allocates stream ids of one parity: odd ids for clients, even ids for servers.
 #[derive(Debug, Clone)]
 pub(crate) struct StreamID {
+    first: u32,
     inner: Arc<AtomicU32>,
 }
 
 impl StreamID {
     pub(crate) fn new(value: u32) -> StreamID {
         let inner = Arc::new(AtomicU32::new(value));
-        StreamID { inner }
+        StreamID {
+            first: value,
+            inner,
+        }
+    }
+
+    /// Returns the next id which is not in use, ids wrap around to the first one after
+    /// `MAX_STREAM_ID`. Returns `None` if every id of the parity is in use.
+    pub(crate) fn next<V>(&self, in_use: &DashMap<u32, V>) -> Option<u32> {
+        let capacity = ((MAX_STREAM_ID - self.first) / 2 + 1) as usize;
+        loop {
+            if in_use.len() >= capacity {
+                return None;
+            }
+            let first = self.first;
+            let sid = self
+                .inner
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| {
+                    if v >= MAX_STREAM_ID - 1 {
+                        Some(first)
+                    } else {
+                        Some(v + 2)
+                    }
+                })
+                .unwrap();
+            if !in_use.contains_key(&sid) {
+                return Some(sid);
+            }
+        }
     }
 
-    pub(crate) fn next(&self) -> u32 {
-        let counter = self.inner.clone();
-        counter.fetch_add(2, Ordering::SeqCst)
+    /// Returns true if the id has the parity of the ids allocated here.
+    pub(crate) fn is_local(&self, sid: u32) -> bool {
+        sid & 1 == self.first & 1
     }
 }
 
@@ -26,21 +69,133 @@ impl From<u32> for StreamID {
     }
 }
 
+/// Limits the concurrent requests of a requester, a permit is held until the request terminates.
 #[derive(Debug, Clone)]
-pub(crate) struct Counter {
-    inner: Arc<AtomicI64>,
+pub(crate) struct RequestLimit {
+    permits: Arc<Semaphore>,
+    overflow: RequestOverflow,
+}
+
+impl RequestLimit {
+    pub(crate) fn new(max: usize, overflow: RequestOverflow) -> RequestLimit {
+        RequestLimit {
+            permits: Arc::new(Semaphore::new(max)),
+            overflow,
+        }
+    }
+
+    pub(crate) async fn acquire(&self) -> Result<OwnedSemaphorePermit> {
+        let permits = self.permits.clone();
+        match self.overflow {
+            RequestOverflow::Queue => Ok(permits.acquire_owned().await?),
+            RequestOverflow::Reject => permits.try_acquire_owned().map_err(|_| {
+                RSocketError::RequestRejected("too many concurrent requests".into()).into()
+            }),
+        }
+    }
+}
+
+/// Credits granted by the peer through REQUEST_N, `REQUEST_MAX` means unbounded.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Credits(u32);
+
+impl Credits {
+    pub(crate) fn new(n: u32) -> Credits {
+        Credits(n.min(REQUEST_MAX))
+    }
+
+    pub(crate) fn add(&mut self, n: u32) {
+        self.0 = self.0.saturating_add(n).min(REQUEST_MAX);
+    }
+
+    pub(crate) fn try_acquire(&mut self) -> bool {
+        match self.0 {
+            0 => false,
+            REQUEST_MAX => true,
+            _ => {
+                self.0 -= 1;
+                true
+            }
+        }
+    }
+
+    /// Waits until a credit can be acquired, returns false once the peer can grant no more.
+    pub(crate) async fn acquire(&mut self, granted: &mut mpsc::UnboundedReceiver<u32>) -> bool {
+        while !self.try_acquire() {
+            match granted.recv().await {
+                Some(n) => self.add(n),
+                None => return false,
+            }
+        }
+        true
+    }
 }
 
-impl Counter {
-    pub(crate) fn new(value: i64) -> Counter {
-        Counter {
-            inner: Arc::new(AtomicI64::new(value)),
+/// The latest lease of a connection, a new lease always replaces the previous one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LeaseTracker {
+    inner: Arc<Mutex<Option<(Instant, u32)>>>,
+}
+
+impl LeaseTracker {
+    pub(crate) fn grant(&self, ttl: Duration, number_of_requests: u32) {
+        let deadline = Instant::now() + ttl;
+        *self.inner.lock().unwrap() = Some((deadline, number_of_requests));
+    }
+
+    pub(crate) fn try_acquire(&self) -> bool {
+        match self.inner.lock().unwrap().as_mut() {
+            Some((deadline, n)) if *n > 0 && Instant::now() < *deadline => {
+                *n -= 1;
+                true
+            }
+            _ => false,
         }
     }
+}
+
+/// Wraps every connection of a client or server: metrics are recorded the closest to the wire,
+/// then frame interceptors are applied.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionLayers {
+    pub(crate) metrics: Option<Arc<dyn MetricsRecorder>>,
+    pub(crate) frame_interceptors: Vec<Arc<dyn FrameInterceptor>>,
+}
+
+impl ConnectionLayers {
+    pub(crate) fn wrap(
+        &self,
+        sink: Box<FrameSink>,
+        stream: Box<FrameStream>,
+    ) -> (Box<FrameSink>, Box<FrameStream>) {
+        let (sink, stream) = match &self.metrics {
+            Some(recorder) => metrics::meter_frames(recorder.clone(), sink, stream),
+            None => (sink, stream),
+        };
+        intercept_frames(&self.frame_interceptors, sink, stream)
+    }
+}
 
-    pub(crate) fn count_down(&self) -> i64 {
-        self.inner.fetch_add(-1, Ordering::SeqCst) - 1
+/// Applies frame interceptors to a connection, the first one is the closest to the wire.
+fn intercept_frames(
+    interceptors: &[Arc<dyn FrameInterceptor>],
+    mut sink: Box<FrameSink>,
+    mut stream: Box<FrameStream>,
+) -> (Box<FrameSink>, Box<FrameStream>) {
+    for it in interceptors {
+        let outbound = it.clone();
+        sink = Box::new(sink.with_flat_map(move |frame| {
+            stream::iter(outbound.intercept(Direction::Outbound, frame).map(Ok))
+        }));
+        let inbound = it.clone();
+        stream = Box::new(stream.filter_map(move |next| {
+            future::ready(match next {
+                Ok(frame) => inbound.intercept(Direction::Inbound, frame).map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+        }));
     }
+    (sink, stream)
 }
 
 #[inline]
@@ -51,3 +206,27 @@ pub(crate) fn debug_frame(snd: bool, f: &Frame) {
         debug!("<=== RCV: {:?}", f);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_id_wraps_and_skips_in_use() {
+        let seq = StreamID {
+            first: 1,
+            inner: Arc::new(AtomicU32::new(MAX_STREAM_ID - 2)),
+        };
+        let in_use = DashMap::new();
+        in_use.insert(1, ());
+        assert_eq!(Some(MAX_STREAM_ID - 2), seq.next(&in_use));
+        assert_eq!(Some(MAX_STREAM_ID), seq.next(&in_use));
+        assert_eq!(Some(3), seq.next(&in_use));
+
+        let seq = StreamID::from(2);
+        assert_eq!(Some(2), seq.next(&in_use));
+        assert_eq!(Some(4), seq.next(&in_use));
+        assert!(seq.is_local(6));
+        assert!(!seq.is_local(7));
+    }
+}
@@ -3,6 +3,7 @@ mod misc;
 mod socket;
 mod spi;
 
-pub(crate) use fragmentation::{Joiner, Splitter, MIN_MTU};
+pub(crate) use fragmentation::{Joiner, Splitter, DEFAULT_MAX_REASSEMBLED_SIZE, MIN_MTU};
+pub(crate) use misc::ConnectionLayers;
 pub(crate) use socket::DuplexSocket;
 pub use spi::*;
@@ -1,33 +1,76 @@
 use std::future::Future;
+use std::ops::Deref;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_stream::stream;
 use async_trait::async_trait;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use dashmap::{mapref::entry::Entry, DashMap};
+use futures::future::{AbortHandle, Abortable};
 use futures::{Sink, SinkExt, Stream, StreamExt};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, Notify, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::time::Instant;
 
-use super::fragmentation::{Joiner, Splitter};
-use super::misc::{debug_frame, Counter, StreamID};
+use super::fragmentation::{Joiner, Splitter, DEFAULT_MAX_REASSEMBLED_SIZE};
+use super::misc::{debug_frame, Credits, LeaseTracker, RequestLimit, StreamID, MAX_STREAM_ID};
 use super::spi::*;
 use crate::error::{self, RSocketError};
-use crate::frame::{self, Body, Frame};
+use crate::frame::{self, Body, Frame, REQUEST_MAX};
+use crate::metrics::{ActiveStream, Interaction, MetricsRecorder, Side};
 use crate::payload::{Payload, SetupPayload};
-use crate::spi::{Flux, RSocket, ServerResponder};
+use crate::spi::{Flux, LeaseStrategy, LimitRate, RSocket, RequestOverflow, ServerResponder};
+use crate::trace::{self, Span};
 use crate::utils::EmptyRSocket;
 use crate::{runtime, Result};
 
+/// The major version of the protocol, peers with other major versions are not supported.
+const SUPPORTED_MAJOR_VERSION: u16 = 1;
+
 #[derive(Clone)]
 pub(crate) struct DuplexSocket {
     seq: StreamID,
     responder: Responder,
     tx: mpsc::UnboundedSender<Frame>,
-    handlers: Arc<DashMap<u32, Handler>>,
+    handlers: Arc<Handlers>,
     canceller: mpsc::Sender<u32>,
     splitter: Option<Splitter>,
     joiners: Arc<DashMap<u32, Joiner>>,
+    limit_rate: LimitRate,
+    // leases received from the peer, requests are sent only within them.
+    lease: Option<LeaseTracker>,
+    // leases granted to the peer, requests beyond them are rejected.
+    granted: Option<LeaseTracker>,
+    lease_strategy: Option<Arc<LeaseStrategy>>,
+    // no more requests will be sent once closed.
+    closed: Arc<AtomicBool>,
+    // the connection-level ERROR received from the peer, later requests fail with it.
+    failure: Arc<Mutex<Option<frame::Error>>>,
+    // SETUP has been sent or accepted, another one is a connection error.
+    established: Arc<AtomicBool>,
+    // limits the streams requested by the peer, requests beyond it are rejected.
+    responding: Option<Arc<Semaphore>>,
+    // limits the requests sent to the peer.
+    requesting: Option<RequestLimit>,
+    // the timeout of request_response sent to the peer.
+    timeout: Option<Duration>,
+    // the deadline of request_stream and request_channel sent to the peer, None by default
+    // since a stream may last as long as the connection.
+    stream_timeout: Option<Duration>,
+    max_reassembled_size: usize,
+    // records requests and streams, None if metrics are disabled.
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    // the span of the connection, every stream gets its own span within it.
+    span: Span,
+}
+
+/// Held by a responder stream until it terminates: releases its slot of the concurrent streams,
+/// and closes it in the metrics.
+struct StreamPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    _active: Option<ActiveStream>,
 }
 
 #[derive(Clone)]
@@ -35,12 +78,119 @@ struct Responder {
     inner: Arc<RwLock<Box<dyn RSocket>>>,
 }
 
+/// Sends CANCEL if the requester gives up a stream which is still in-flight.
+struct CancelGuard {
+    sid: u32,
+    tx: mpsc::UnboundedSender<Frame>,
+    handlers: Arc<Handlers>,
+}
+
+/// The handlers of in-flight streams, notifies the waiters of `drained` once a handler is
+/// removed.
+#[derive(Debug, Default)]
+struct Handlers {
+    inner: DashMap<u32, Handler>,
+    released: Notify,
+}
+
 #[derive(Debug)]
 enum Handler {
     ReqRR(oneshot::Sender<Result<Option<Payload>>>),
-    ResRR(Counter),
-    ReqRS(mpsc::Sender<Result<Payload>>),
-    ReqRC(mpsc::Sender<Result<Payload>>),
+    ResRR(AbortHandle),
+    ReqRS(mpsc::UnboundedSender<Result<Payload>>),
+    ResRS(mpsc::UnboundedSender<u32>, AbortHandle),
+    ReqRC(Channel),
+    ResRC(Channel),
+}
+
+/// The state of an in-flight stream, a terminated stream has no handler at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamState {
+    /// We have sent the request, the peer responds with PAYLOAD or ERROR.
+    Requested,
+    /// The peer has sent a REQUEST_RESPONSE or REQUEST_STREAM, we are responding.
+    Responding,
+    /// Both halves of a channel are open.
+    Active,
+    /// Our half of a channel has terminated, only the peer keeps sending.
+    HalfClosedLocal,
+    /// The peer's half of a channel has terminated, only we keep sending.
+    HalfClosedRemote,
+}
+
+/// Both directions of a REQUEST_CHANNEL, each half is released once it terminates.
+#[derive(Debug)]
+struct Channel {
+    inbound: Option<mpsc::UnboundedSender<Result<Payload>>>,
+    outbound: Option<(mpsc::UnboundedSender<u32>, AbortHandle)>,
+}
+
+impl Handler {
+    fn state(&self) -> StreamState {
+        match self {
+            Handler::ReqRR(_) | Handler::ReqRS(_) => StreamState::Requested,
+            Handler::ResRR(_) | Handler::ResRS(..) => StreamState::Responding,
+            Handler::ReqRC(c) | Handler::ResRC(c) => match (&c.inbound, &c.outbound) {
+                (Some(_), None) => StreamState::HalfClosedLocal,
+                (None, Some(_)) => StreamState::HalfClosedRemote,
+                _ => StreamState::Active,
+            },
+        }
+    }
+
+    /// Terminates the stream locally: requesters receive the error, responders are aborted.
+    fn terminate(self, e: RSocketError) {
+        match self {
+            Handler::ReqRR(tx) => {
+                let _ = tx.send(Err(e.into()));
+            }
+            Handler::ReqRS(tx) => {
+                let _ = tx.send(Err(e.into()));
+            }
+            Handler::ResRR(abort) | Handler::ResRS(_, abort) => abort.abort(),
+            Handler::ReqRC(c) | Handler::ResRC(c) => {
+                if let Some((_, abort)) = c.outbound {
+                    abort.abort();
+                }
+                if let Some(tx) = c.inbound {
+                    let _ = tx.send(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+impl Handlers {
+    /// Removes the handler, shadows `DashMap::remove` so the removal is notified.
+    fn remove(&self, sid: &u32) -> Option<(u32, Handler)> {
+        let removed = self.inner.remove(sid);
+        self.release();
+        removed
+    }
+
+    /// Notifies the waiters of `drained`, must be called once a handler is removed through
+    /// an entry.
+    fn release(&self) {
+        self.released.notify_waiters();
+    }
+
+    async fn drained(&self, deadline: Instant) {
+        loop {
+            // registered before checking, so a removal in between is not missed.
+            let released = self.released.notified();
+            if self.inner.is_empty() || tokio::time::timeout_at(deadline, released).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Deref for Handlers {
+    type Target = DashMap<u32, Handler>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
 }
 
 impl DuplexSocket {
@@ -48,6 +198,7 @@ impl DuplexSocket {
         first_stream_id: u32,
         tx: mpsc::UnboundedSender<Frame>,
         splitter: Option<Splitter>,
+        limit_rate: LimitRate,
     ) -> DuplexSocket {
         let (canceller_tx, canceller_rx) = mpsc::channel::<u32>(32);
         let socket = DuplexSocket {
@@ -55,22 +206,103 @@ impl DuplexSocket {
             tx,
             canceller: canceller_tx,
             responder: Responder::new(),
-            handlers: Arc::new(DashMap::new()),
+            handlers: Arc::new(Handlers::default()),
             joiners: Arc::new(DashMap::new()),
             splitter,
+            limit_rate,
+            lease: None,
+            granted: None,
+            lease_strategy: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            failure: Arc::new(Mutex::new(None)),
+            established: Arc::new(AtomicBool::new(false)),
+            responding: None,
+            requesting: None,
+            timeout: None,
+            stream_timeout: None,
+            max_reassembled_size: DEFAULT_MAX_REASSEMBLED_SIZE,
+            metrics: None,
+            span: Span::none(),
         };
 
-        let cloned_socket = socket.clone();
+        // only the handlers are held, so the loop stops once the socket is dropped.
+        let handlers = socket.handlers.clone();
 
         runtime::spawn(async move {
-            cloned_socket.loop_canceller(canceller_rx).await;
+            Self::loop_canceller(&handlers, canceller_rx).await;
         });
 
         socket
     }
 
+    /// Requires leases from the peer before sending requests, must be called before cloning.
+    pub(crate) fn honor_lease(&mut self) {
+        self.lease = Some(LeaseTracker::default());
+    }
+
+    /// Grants leases to peers which require leasing, must be called before cloning.
+    pub(crate) fn set_lease_strategy(&mut self, strategy: Arc<LeaseStrategy>) {
+        self.lease_strategy = Some(strategy);
+    }
+
+    /// Limits the concurrent streams requested by the peer, must be called before cloning.
+    pub(crate) fn limit_streams(&mut self, max: usize) {
+        self.responding = Some(Arc::new(Semaphore::new(max)));
+    }
+
+    /// Limits the concurrent requests sent to the peer, must be called before cloning.
+    pub(crate) fn limit_requests(&mut self, max: usize, overflow: RequestOverflow) {
+        self.requesting = Some(RequestLimit::new(max, overflow));
+    }
+
+    /// Limits the size of fragmented payloads received from the peer, must be called before
+    /// cloning.
+    pub(crate) fn set_max_reassembled_size(&mut self, size: usize) {
+        self.max_reassembled_size = size;
+    }
+
+    /// Sets the default timeout of request_response, must be called before cloning.
+    pub(crate) fn set_request_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Records requests and streams to the recorder, must be called before cloning.
+    pub(crate) fn set_metrics(&mut self, recorder: Arc<dyn MetricsRecorder>) {
+        self.metrics = Some(recorder);
+    }
+
+    /// Sets the span of the connection, must be called before cloning.
+    pub(crate) fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    pub(crate) fn limit_rate(&self) -> LimitRate {
+        self.limit_rate
+    }
+
+    /// Returns a clone whose requests use the limit rate instead of the default, and expire after
+    /// the timeout if any, the timeout of a stream covers the whole stream.
+    pub(crate) fn with_overrides(
+        &self,
+        limit_rate: LimitRate,
+        timeout: Option<Duration>,
+    ) -> DuplexSocket {
+        let mut socket = self.clone();
+        socket.limit_rate = limit_rate;
+        if let Some(timeout) = timeout {
+            socket.timeout = Some(timeout);
+            socket.stream_timeout = Some(timeout);
+        }
+        socket
+    }
+
     pub(crate) async fn setup(&mut self, setup: SetupPayload) {
-        let mut bu = frame::Setup::builder(0, 0);
+        let flag = if self.lease.is_some() {
+            Frame::FLAG_LEASE
+        } else {
+            0
+        };
+        let mut bu = frame::Setup::builder(0, flag);
         if let Some(s) = setup.data_mime_type() {
             bu = bu.set_mime_data(s);
         }
@@ -79,6 +311,9 @@ impl DuplexSocket {
         }
         bu = bu.set_keepalive(setup.keepalive_interval());
         bu = bu.set_lifetime(setup.keepalive_lifetime());
+        if let Some(b) = setup.resume_token() {
+            bu = bu.set_token(b.clone());
+        }
         let (d, m) = setup.split();
         if let Some(b) = d {
             bu = bu.set_data(b);
@@ -86,7 +321,104 @@ impl DuplexSocket {
         if let Some(b) = m {
             bu = bu.set_metadata(b);
         }
-        self.tx.send(bu.build()).expect("Send setup failed");
+        self.established.store(true, Ordering::SeqCst);
+        if let Err(e) = self.tx.send(bu.build()) {
+            error!("send setup frame failed: {}", e);
+        }
+    }
+
+    /// Stops sending new requests, outstanding streams are not affected.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops both sending and accepting new requests, a peer which honors leases receives a lease
+    /// without any request.
+    pub(crate) fn drain(&self, ttl: Duration) {
+        self.close();
+        if let Some(granted) = &self.granted {
+            granted.grant(ttl, 0);
+            let ttl = ttl.as_millis().min(u128::from(u32::MAX)) as u32;
+            let sending = frame::Lease::builder(0, 0)
+                .set_ttl(ttl)
+                .set_number_of_requests(0)
+                .build();
+            if let Err(e) = self.tx.send(sending) {
+                error!("send LEASE failed: {}", e);
+            }
+        }
+    }
+
+    /// Waits until no stream is in-flight in either direction, or until the deadline.
+    pub(crate) async fn drained(&self, deadline: Instant) {
+        self.handlers.drained(deadline).await
+    }
+
+    /// Terminates all the in-flight streams locally: requesters receive the error, responders are
+    /// aborted. Nothing will be sent to the peer.
+    pub(crate) fn terminate<F>(&self, err: F)
+    where
+        F: Fn() -> RSocketError,
+    {
+        self.joiners.clear();
+        let sids: Vec<u32> = self.handlers.iter().map(|it| *it.key()).collect();
+        for sid in sids {
+            if let Some((_, handler)) = self.handlers.remove(&sid) {
+                handler.terminate(err());
+            }
+        }
+    }
+
+    /// Terminates a stream which receives an illegal frame for its state with ERROR[INVALID].
+    fn on_invalid(&self, sid: u32, errmsg: &str) {
+        warn!("invalid frame on stream {}: {}", sid, errmsg);
+        self.joiners.remove(&sid);
+        if let Some((_, handler)) = self.handlers.remove(&sid) {
+            handler.terminate(RSocketError::RequestInvalid(errmsg.into()));
+        }
+        let sending = frame::Error::builder(sid, 0)
+            .set_code(error::ERR_INVALID)
+            .set_data(Bytes::from(errmsg.to_owned()))
+            .build();
+        if let Err(e) = self.tx.send(sending) {
+            error!("send ERROR frame failed: {}", e);
+        }
+    }
+
+    fn state_of(&self, sid: u32) -> Option<StreamState> {
+        self.handlers.get(&sid).map(|it| it.state())
+    }
+
+    fn next_stream_id(&self) -> Result<u32> {
+        self.seq
+            .next(&self.handlers)
+            .ok_or_else(|| RSocketError::StreamIdExhausted.into())
+    }
+
+    /// Checks the id of a new request: ids of the wrong parity break the connection, ids of
+    /// in-flight streams are rejected with ERROR[INVALID].
+    fn check_request_id(&self, sid: u32) -> Result<bool> {
+        if sid == 0 || sid > MAX_STREAM_ID || self.seq.is_local(sid) {
+            let errmsg = format!("invalid stream id {}", sid);
+            self.send_error(error::ERR_CONN_FAILED, &errmsg);
+            return Err(RSocketError::ConnectionException(errmsg).into());
+        }
+        if self.handlers.contains_key(&sid) {
+            self.on_invalid(sid, "stream id is in use");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Sends a connection-level ERROR frame.
+    pub(crate) fn send_error(&self, code: u32, errmsg: &str) {
+        let sending = frame::Error::builder(0, 0)
+            .set_code(code)
+            .set_data(Bytes::from(errmsg.to_owned()))
+            .build();
+        if let Err(e) = self.tx.send(sending) {
+            error!("send ERROR frame failed: {}", e);
+        }
     }
 
     #[inline]
@@ -95,9 +427,9 @@ impl DuplexSocket {
     }
 
     #[inline]
-    async fn loop_canceller(&self, mut rx: mpsc::Receiver<u32>) {
+    async fn loop_canceller(handlers: &Handlers, mut rx: mpsc::Receiver<u32>) {
         while let Some(sid) = rx.recv().await {
-            self.handlers.remove(&sid);
+            handlers.remove(&sid);
         }
     }
 
@@ -106,57 +438,121 @@ impl DuplexSocket {
         frame: Frame,
         acceptor: Option<&ServerResponder>,
     ) -> Result<()> {
-        if let Some(frame) = self.join_frame(frame).await {
-            self.process_once(frame, acceptor).await;
+        match self.join_frame(frame).await {
+            Some(frame) => self.process_once(frame, acceptor).await,
+            None => Ok(()),
         }
-        Ok(())
     }
 
     #[inline]
-    async fn process_once(&mut self, msg: Frame, acceptor: Option<&ServerResponder>) {
+    async fn process_once(&mut self, msg: Frame, acceptor: Option<&ServerResponder>) -> Result<()> {
         let sid = msg.get_stream_id();
         let flag = msg.get_flag();
         debug_frame(false, &msg);
         match msg.get_body() {
             Body::Setup(v) => {
-                if let Err(e) = self
-                    .on_setup(acceptor, sid, flag, SetupPayload::from(v))
-                    .await
-                {
-                    let errmsg = format!("{}", e);
+                if self.established.load(Ordering::SeqCst) {
+                    let errmsg = "duplicate SETUP";
+                    self.send_error(error::ERR_CONN_FAILED, errmsg);
+                    return Err(RSocketError::ConnectionException(errmsg.into()).into());
+                }
+                let mut setup = SetupPayload::from(v);
+                setup.set_flags(flag);
+                if let Err(e) = self.on_setup(acceptor, sid, flag, setup).await {
+                    // acceptors may choose the error code by returning a typed rejection.
+                    let (code, errmsg) = match e.downcast_ref::<RSocketError>() {
+                        Some(RSocketError::InvalidSetup(s)) => {
+                            (error::ERR_INVALID_SETUP, s.clone())
+                        }
+                        Some(RSocketError::UnsupportedSetup(s)) => {
+                            (error::ERR_UNSUPPORTED_SETUP, s.clone())
+                        }
+                        Some(RSocketError::RejectedSetup(s)) => {
+                            (error::ERR_REJECT_SETUP, s.clone())
+                        }
+                        _ => (error::ERR_REJECT_SETUP, format!("{}", e)),
+                    };
                     let sending = frame::Error::builder(0, 0)
-                        .set_code(error::ERR_REJECT_SETUP)
+                        .set_code(code)
                         .set_data(Bytes::from(errmsg))
                         .build();
-                    self.tx.send(sending).expect("Reject setup failed");
-                    return;
+                    if let Err(e) = self.tx.send(sending) {
+                        error!("reject setup failed: {}", e);
+                    }
+                    return Err(e);
                 }
+                self.established.store(true, Ordering::SeqCst);
             }
-            Body::Resume(v) => {
-                // TODO: support resume
-            }
-            Body::ResumeOK(v) => {
-                // TODO: support resume ok
+            Body::Resume(_) | Body::ResumeOK(_) => {
+                // resumption is handled before frames reach the socket.
+                warn!("ignore unexpected RESUME/RESUME_OK frame");
             }
             Body::MetadataPush(v) => {
+                if sid != 0 {
+                    let errmsg = format!("METADATA_PUSH on stream {}", sid);
+                    self.send_error(error::ERR_CONN_FAILED, &errmsg);
+                    return Err(RSocketError::ConnectionException(errmsg).into());
+                }
+                self.count_request(Side::Responder, Interaction::MetadataPush);
                 let input = Payload::from(v);
                 self.on_metadata_push(input).await;
             }
             Body::RequestFNF(v) => {
+                if !self.check_request_id(sid)? || !self.check_granted(sid, false) {
+                    return Ok(());
+                }
+                self.count_request(Side::Responder, Interaction::FireAndForget);
                 let input = Payload::from(v);
                 self.on_fire_and_forget(sid, input).await;
             }
             Body::RequestResponse(v) => {
+                if !self.check_request_id(sid)? {
+                    return Ok(());
+                }
+                let permit = match self.admit_stream(sid) {
+                    Some(it) if self.check_granted(sid, true) => {
+                        self.stream_permit(it, Interaction::RequestResponse)
+                    }
+                    _ => return Ok(()),
+                };
                 let input = Payload::from(v);
-                self.on_request_response(sid, flag, input).await;
+                self.on_request_response(sid, flag, input, permit).await;
             }
             Body::RequestStream(v) => {
+                if !self.check_request_id(sid)? {
+                    return Ok(());
+                }
+                if v.get_initial_request_n() == 0 {
+                    self.on_invalid(sid, "initial REQUEST_N of 0");
+                    return Ok(());
+                }
+                let permit = match self.admit_stream(sid) {
+                    Some(it) if self.check_granted(sid, true) => {
+                        self.stream_permit(it, Interaction::RequestStream)
+                    }
+                    _ => return Ok(()),
+                };
+                let n = v.get_initial_request_n();
                 let input = Payload::from(v);
-                self.on_request_stream(sid, flag, input).await;
+                self.on_request_stream(sid, flag, n, input, permit).await;
             }
             Body::RequestChannel(v) => {
+                if !self.check_request_id(sid)? {
+                    return Ok(());
+                }
+                if v.get_initial_request_n() == 0 {
+                    self.on_invalid(sid, "initial REQUEST_N of 0");
+                    return Ok(());
+                }
+                let permit = match self.admit_stream(sid) {
+                    Some(it) if self.check_granted(sid, true) => {
+                        self.stream_permit(it, Interaction::RequestChannel)
+                    }
+                    _ => return Ok(()),
+                };
+                let n = v.get_initial_request_n();
                 let input = Payload::from(v);
-                self.on_request_channel(sid, flag, input).await;
+                self.on_request_channel(sid, flag, n, input, permit).await;
             }
             Body::Payload(v) => {
                 let input = Payload::from(v);
@@ -169,21 +565,133 @@ impl DuplexSocket {
                 }
             }
             Body::RequestN(v) => {
-                // TODO: support RequestN
+                self.on_request_n(sid, flag, v).await;
+            }
+            Body::Error(v) if sid == 0 => {
+                return Err(self.on_connection_error(v).into());
             }
             Body::Error(v) => {
-                // TODO: support error
                 self.on_error(sid, flag, v).await;
             }
             Body::Cancel() => {
                 self.on_cancel(sid, flag).await;
             }
             Body::Lease(v) => {
-                // TODO: support Lease
+                self.on_lease(v);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn on_lease(&self, input: frame::Lease) {
+        match &self.lease {
+            Some(lease) => {
+                let ttl = Duration::from_millis(u64::from(input.get_ttl()));
+                lease.grant(ttl, input.get_number_of_requests());
+            }
+            None => warn!("ignore LEASE: leasing is disabled"),
+        }
+    }
+
+    /// Checks the lease granted to the peer, the request is rejected if it exceeds the lease or
+    /// the socket has been closed.
+    #[inline]
+    fn check_granted(&self, sid: u32, respond: bool) -> bool {
+        let errmsg = if self.closed.load(Ordering::SeqCst) {
+            "socket has been closed"
+        } else {
+            match &self.granted {
+                Some(granted) if !granted.try_acquire() => "no available lease",
+                _ => return true,
+            }
+        };
+        warn!("reject request {}: {}", sid, errmsg);
+        if respond {
+            let sending = frame::Error::builder(sid, 0)
+                .set_code(error::ERR_REJECTED)
+                .set_data(Bytes::from(errmsg))
+                .build();
+            if let Err(e) = self.tx.send(sending) {
+                error!("reject request failed: {}", e);
+            }
+        }
+        false
+    }
+
+    /// Acquires a permit for a stream requested by the peer, the request is rejected with
+    /// ERROR[REJECTED] if the concurrent streams reach the limit.
+    fn admit_stream(&self, sid: u32) -> Option<Option<OwnedSemaphorePermit>> {
+        let responding = match &self.responding {
+            Some(it) => it.clone(),
+            None => return Some(None),
+        };
+        match responding.try_acquire_owned() {
+            Ok(permit) => Some(Some(permit)),
+            Err(_) => {
+                let errmsg = "too many concurrent streams";
+                warn!("reject request {}: {}", sid, errmsg);
+                let sending = frame::Error::builder(sid, 0)
+                    .set_code(error::ERR_REJECTED)
+                    .set_data(Bytes::from(errmsg))
+                    .build();
+                if let Err(e) = self.tx.send(sending) {
+                    error!("reject request failed: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    fn stream_permit(
+        &self,
+        permit: Option<OwnedSemaphorePermit>,
+        interaction: Interaction,
+    ) -> StreamPermit {
+        StreamPermit {
+            _permit: permit,
+            _active: self.track(Side::Responder, interaction),
+        }
+    }
+
+    /// Opens a stream in the metrics, it is closed once the returned guard is dropped.
+    fn track(&self, side: Side, interaction: Interaction) -> Option<ActiveStream> {
+        self.metrics
+            .clone()
+            .map(|recorder| ActiveStream::open(recorder, side, interaction))
+    }
+
+    fn stream_span(&self, sid: u32, side: Side, interaction: Interaction) -> Span {
+        trace::stream(&self.span, sid, side, interaction)
+    }
+
+    fn count_request(&self, side: Side, interaction: Interaction) {
+        if let Some(recorder) = &self.metrics {
+            recorder.request(side, interaction);
+        }
+    }
+
+    /// Checks that the socket is still open, and acquires a lease from the peer before sending a
+    /// request.
+    #[inline]
+    fn admit_request(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            if let Some(e) = self.failure.lock().unwrap().as_ref() {
+                return Err(RSocketError::from_error_frame(e.get_code(), e.get_data()).into());
+            }
+            return Err(RSocketError::ConnectionClosed("socket has been closed".into()).into());
+        }
+        match &self.lease {
+            Some(lease) if !lease.try_acquire() => {
+                Err(RSocketError::RequestRejected("no available lease".into()).into())
             }
+            _ => Ok(()),
         }
     }
 
+    /// Collects the fragments of a stream, and returns the reassembled frame once the last
+    /// fragment arrives. Fragments interrupted by a new request, or whose payload exceeds the
+    /// limit, terminate the stream with ERROR[INVALID].
     #[inline]
     async fn join_frame(&self, input: Frame) -> Option<Frame> {
         let (is_follow, is_payload) = input.is_followable_or_payload();
@@ -191,69 +699,36 @@ impl DuplexSocket {
             return Some(input);
         }
         let sid = input.get_stream_id();
-        if input.get_flag() & Frame::FLAG_FOLLOW != 0 {
-            // TODO: check conflict
-            self.joiners
-                .entry(sid)
-                .or_insert_with(Joiner::new)
-                .push(input);
+        let follow = input.get_flag() & Frame::FLAG_FOLLOW != 0;
+        let mut joiner = match self.joiners.remove(&sid) {
+            Some((_, joiner)) if is_payload => joiner,
+            Some(_) => {
+                self.on_invalid(sid, "fragments interrupted by another request");
+                return None;
+            }
+            None if follow => Joiner::new(),
+            None => return Some(input),
+        };
+        joiner.push(input);
+        if joiner.size() > self.max_reassembled_size {
+            self.on_invalid(sid, "reassembled payload is too large");
             return None;
         }
-
-        if !is_payload {
-            return Some(input);
+        if follow {
+            self.joiners.insert(sid, joiner);
+            return None;
         }
+        Some(joiner.join())
+    }
 
-        match self.joiners.remove(&sid) {
-            None => Some(input),
-            Some((_, mut joiner)) => {
-                joiner.push(input);
-                let flag = joiner.get_flag();
-                let first = joiner.first();
-                match &first.body {
-                    frame::Body::RequestResponse(_) => {
-                        let pa: Payload = joiner.into();
-                        let result = frame::RequestResponse::builder(sid, flag)
-                            .set_all(pa.split())
-                            .build();
-                        Some(result)
-                    }
-                    frame::Body::RequestStream(b) => {
-                        let n = b.get_initial_request_n();
-                        let pa: Payload = joiner.into();
-                        let result = frame::RequestStream::builder(sid, flag)
-                            .set_initial_request_n(n)
-                            .set_all(pa.split())
-                            .build();
-                        Some(result)
-                    }
-                    frame::Body::RequestFNF(_) => {
-                        let pa: Payload = joiner.into();
-                        let result = frame::RequestFNF::builder(sid, flag)
-                            .set_all(pa.split())
-                            .build();
-                        Some(result)
-                    }
-                    frame::Body::RequestChannel(b) => {
-                        let n = b.get_initial_request_n();
-                        let pa: Payload = joiner.into();
-                        let result = frame::RequestChannel::builder(sid, flag)
-                            .set_initial_request_n(n)
-                            .set_all(pa.split())
-                            .build();
-                        Some(result)
-                    }
-                    frame::Body::Payload(b) => {
-                        let pa: Payload = joiner.into();
-                        let result = frame::Payload::builder(sid, flag)
-                            .set_all(pa.split())
-                            .build();
-                        Some(result)
-                    }
-                    _ => unreachable!(),
-                }
-            }
-        }
+    /// An ERROR on stream 0 terminates the connection: all the in-flight streams fail with it,
+    /// so do the requests sent afterwards.
+    fn on_connection_error(&self, input: frame::Error) -> RSocketError {
+        let (code, data) = (input.get_code(), input.get_data().cloned());
+        *self.failure.lock().unwrap() = Some(input);
+        self.close();
+        self.terminate(|| RSocketError::from_error_frame(code, data.as_ref()));
+        RSocketError::from_error_frame(code, data.as_ref())
     }
 
     #[inline]
@@ -261,86 +736,167 @@ impl DuplexSocket {
         self.joiners.remove(&sid);
         // pick handler
         if let Some((_, handler)) = self.handlers.remove(&sid) {
-            let desc = input.get_data_utf8().unwrap().to_owned();
-            let e = RSocketError::must_new_from_code(input.get_code(), desc);
+            let e = RSocketError::from_error_frame(input.get_code(), input.get_data());
             match handler {
-                Handler::ReqRR(tx) => tx.send(Err(e.into())).expect("Send RR failed"),
-                Handler::ResRR(_) => unreachable!(),
-                Handler::ReqRS(tx) => tx.send(Err(e.into())).await.expect("Send RS failed"),
-                Handler::ReqRC(tx) => tx.send(Err(e.into())).await.expect("Send RC failed"),
+                Handler::ReqRR(tx) => {
+                    if tx.send(Err(e.into())).is_err() {
+                        debug!("REQUEST_RESPONSE {} has been dropped", sid);
+                    }
+                }
+                Handler::ResRR(abort) | Handler::ResRS(_, abort) => {
+                    // a requester should CANCEL rather than ERROR, but the stream is over anyway.
+                    warn!("stream {} terminated by requester: {}", sid, e);
+                    abort.abort();
+                }
+                Handler::ReqRS(tx) => {
+                    if tx.send(Err(e.into())).is_err() {
+                        debug!("stream {} has been dropped", sid);
+                    }
+                }
+                Handler::ReqRC(c) | Handler::ResRC(c) => {
+                    // ERROR terminates both directions of a channel.
+                    if let Some((_, abort)) = c.outbound {
+                        abort.abort();
+                    }
+                    if let Some(tx) = c.inbound {
+                        if tx.send(Err(e.into())).is_err() {
+                            debug!("stream {} has been dropped", sid);
+                        }
+                    }
+                }
             }
         }
     }
 
     #[inline]
     async fn on_cancel(&mut self, sid: u32, _flag: u16) {
+        if self.state_of(sid) == Some(StreamState::Requested) {
+            self.on_invalid(sid, "CANCEL from responder");
+            return;
+        }
         self.joiners.remove(&sid);
-        if let Some((_, handler)) = self.handlers.remove(&sid) {
-            let e: Result<_> =
-                Err(RSocketError::RequestCancelled("request has been cancelled".into()).into());
-            match handler {
-                Handler::ReqRR(sender) => {
-                    info!("REQUEST_RESPONSE {} cancelled!", sid);
-                    sender.send(e).unwrap();
-                }
-                Handler::ResRR(c) => {
-                    let lefts = c.count_down();
-                    info!("REQUEST_RESPONSE {} cancelled: lefts={}", sid, lefts);
-                }
-                Handler::ReqRS(sender) => {
-                    info!("REQUEST_STREAM {} cancelled!", sid);
-                }
-                Handler::ReqRC(sender) => {
+        let handler = match self.handlers.entry(sid) {
+            Entry::Occupied(mut o) => match o.get_mut() {
+                Handler::ReqRC(c) | Handler::ResRC(c) => {
+                    // CANCEL only stops the outbound, the inbound keeps going until it terminates.
                     info!("REQUEST_CHANNEL {} cancelled!", sid);
+                    if let Some((_, abort)) = c.outbound.take() {
+                        abort.abort();
+                    }
+                    if c.inbound.is_none() {
+                        o.remove();
+                    }
+                    None
                 }
-            };
+                _ => Some(o.remove()),
+            },
+            Entry::Vacant(_) => None,
+        };
+        self.handlers.release();
+        if let Some(handler) = handler {
+            info!("stream {} cancelled!", sid);
+            handler.terminate(RSocketError::RequestCancelled(
+                "request has been cancelled".into(),
+            ));
         }
     }
 
     #[inline]
     async fn on_payload(&mut self, sid: u32, flag: u16, input: Payload) {
+        match self.state_of(sid) {
+            // the stream may have been terminated locally, e.g. cancelled.
+            None => {
+                debug!("ignore PAYLOAD {}: no such stream", sid);
+                return;
+            }
+            Some(_) if flag & (Frame::FLAG_NEXT | Frame::FLAG_COMPLETE) == 0 => {
+                self.on_invalid(sid, "PAYLOAD without NEXT or COMPLETE");
+                return;
+            }
+            Some(StreamState::Responding) => {
+                self.on_invalid(sid, "PAYLOAD from requester");
+                return;
+            }
+            Some(StreamState::HalfClosedRemote) => {
+                self.on_invalid(sid, "PAYLOAD after COMPLETE");
+                return;
+            }
+            _ => (),
+        }
         match self.handlers.entry(sid) {
-            Entry::Occupied(o) => {
-                match o.get() {
-                    Handler::ReqRR(_) => match o.remove() {
-                        Handler::ReqRR(sender) => {
-                            if flag & Frame::FLAG_NEXT != 0 {
-                                sender.send(Ok(Some(input))).unwrap();
+            Entry::Occupied(mut o) => {
+                match o.get_mut() {
+                    Handler::ReqRR(_) => {
+                        if let Handler::ReqRR(sender) = o.remove() {
+                            let res = if flag & Frame::FLAG_NEXT != 0 {
+                                sender.send(Ok(Some(input)))
                             } else {
-                                sender.send(Ok(None)).unwrap();
+                                sender.send(Ok(None))
+                            };
+                            if res.is_err() {
+                                debug!("REQUEST_RESPONSE {} has been dropped", sid);
                             }
                         }
-                        _ => unreachable!(),
-                    },
-                    Handler::ResRR(c) => unreachable!(),
+                    }
                     Handler::ReqRS(sender) => {
-                        if flag & Frame::FLAG_NEXT != 0 {
-                            sender
-                                .clone()
-                                .send(Ok(input))
-                                .await
-                                .expect("Send payload response failed.");
+                        if flag & Frame::FLAG_NEXT != 0 && sender.send(Ok(input)).is_err() {
+                            debug!("REQUEST_STREAM {} has been dropped", sid);
                         }
                         if flag & Frame::FLAG_COMPLETE != 0 {
                             o.remove();
                         }
                     }
-                    Handler::ReqRC(sender) => {
-                        // TODO: support channel
-                        if flag & Frame::FLAG_NEXT != 0 {
-                            sender
-                                .clone()
-                                .send(Ok(input))
-                                .await
-                                .expect("Send payload response failed");
+                    Handler::ReqRC(c) | Handler::ResRC(c) => {
+                        if let Some(sender) = &c.inbound {
+                            if flag & Frame::FLAG_NEXT != 0 && sender.send(Ok(input)).is_err() {
+                                debug!("REQUEST_CHANNEL {} has been dropped", sid);
+                            }
                         }
                         if flag & Frame::FLAG_COMPLETE != 0 {
-                            o.remove();
+                            // the peer has finished sending, our outbound may still be alive.
+                            c.inbound = None;
+                            if c.outbound.is_none() {
+                                o.remove();
+                            }
                         }
                     }
+                    // checked above.
+                    Handler::ResRR(_) | Handler::ResRS(..) => (),
+                }
+            }
+            // removed concurrently.
+            Entry::Vacant(_) => debug!("ignore PAYLOAD {}: no such stream", sid),
+        }
+        self.handlers.release();
+    }
+
+    #[inline]
+    async fn on_request_n(&mut self, sid: u32, _flag: u16, input: frame::RequestN) {
+        if self.state_of(sid) == Some(StreamState::Requested) {
+            self.on_invalid(sid, "REQUEST_N from responder");
+            return;
+        }
+        if input.get_n() == 0 && self.handlers.contains_key(&sid) {
+            self.on_invalid(sid, "REQUEST_N of 0");
+            return;
+        }
+        if let Some(handler) = self.handlers.get(&sid) {
+            match handler.value() {
+                Handler::ResRS(credits, _)
+                | Handler::ReqRC(Channel {
+                    outbound: Some((credits, _)),
+                    ..
+                })
+                | Handler::ResRC(Channel {
+                    outbound: Some((credits, _)),
+                    ..
+                }) => {
+                    if let Err(e) = credits.send(input.get_n()) {
+                        debug!("deliver REQUEST_N {} failed: {}", sid, e);
+                    }
                 }
+                _ => (),
             }
-            Entry::Vacant(_) => warn!("invalid payload id {}: no such request!", sid),
         }
     }
 
@@ -350,161 +906,353 @@ impl DuplexSocket {
 
     #[inline]
     async fn on_setup(
-        &self,
+        &mut self,
         acceptor: Option<&ServerResponder>,
         sid: u32,
         flag: u16,
         setup: SetupPayload,
     ) -> Result<()> {
-        match acceptor {
-            None => {
-                self.responder.set(Box::new(EmptyRSocket)).await;
-                Ok(())
-            }
-            Some(gen) => match gen(setup, Box::new(self.clone())) {
-                Ok(it) => {
-                    self.responder.set(it).await;
-                    Ok(())
+        Self::validate_setup(&setup)?;
+        let strategy = if flag & Frame::FLAG_LEASE != 0 {
+            match &self.lease_strategy {
+                Some(it) => Some(it.clone()),
+                None => {
+                    return Err(
+                        RSocketError::UnsupportedSetup("lease is not supported".into()).into(),
+                    )
                 }
-                Err(e) => Err(e),
-            },
+            }
+        } else {
+            None
+        };
+        let responder: Box<dyn RSocket> = match acceptor {
+            None => Box::new(EmptyRSocket),
+            Some(gen) => gen(setup, Box::new(self.clone()))?,
+        };
+        self.responder.set(responder).await;
+        if let Some(strategy) = strategy {
+            self.start_lease(strategy);
+        }
+        Ok(())
+    }
+
+    fn validate_setup(setup: &SetupPayload) -> Result<()> {
+        let version = setup.version();
+        if version.get_major() != SUPPORTED_MAJOR_VERSION {
+            let errmsg = format!(
+                "unsupported version {}.{}",
+                version.get_major(),
+                version.get_minor()
+            );
+            return Err(RSocketError::UnsupportedSetup(errmsg).into());
+        }
+        // both of them are unsigned 31-bit integers in milliseconds, and must be positive.
+        let max = Duration::from_millis(i32::MAX as u64);
+        let interval = setup.keepalive_interval();
+        if interval.is_zero() || interval > max {
+            return Err(RSocketError::InvalidSetup("invalid keepalive interval".into()).into());
+        }
+        let lifetime = setup.keepalive_lifetime();
+        if lifetime.is_zero() || lifetime > max {
+            return Err(RSocketError::InvalidSetup("invalid keepalive lifetime".into()).into());
         }
+        let is_ascii = |mime: Option<&str>| mime.map(|it| it.is_ascii()).unwrap_or(false);
+        if !is_ascii(setup.metadata_mime_type()) || !is_ascii(setup.data_mime_type()) {
+            return Err(RSocketError::InvalidSetup("invalid mime type".into()).into());
+        }
+        Ok(())
+    }
+
+    fn start_lease(&mut self, strategy: Arc<LeaseStrategy>) {
+        let granted = LeaseTracker::default();
+        self.granted = Some(granted.clone());
+        let tx = self.tx.clone();
+        let closed = self.closed.clone();
+        runtime::spawn(async move {
+            let mut leases = strategy();
+            while let Some(next) = leases.next().await {
+                if closed.load(Ordering::SeqCst) {
+                    break;
+                }
+                granted.grant(next.ttl(), next.number_of_requests());
+                let ttl = next.ttl().as_millis().min(u128::from(u32::MAX)) as u32;
+                let mut bu = frame::Lease::builder(0, 0)
+                    .set_ttl(ttl)
+                    .set_number_of_requests(next.number_of_requests());
+                if let Some(b) = next.metadata() {
+                    bu = bu.set_metadata(b.clone());
+                }
+                if let Err(e) = tx.send(bu.build()) {
+                    debug!("send LEASE failed: {}", e);
+                    break;
+                }
+            }
+        });
     }
 
     #[inline]
     async fn on_fire_and_forget(&mut self, sid: u32, input: Payload) {
-        if let Err(e) = self.responder.fire_and_forget(input).await {
+        let span = self.stream_span(sid, Side::Responder, Interaction::FireAndForget);
+        let responding = self.responder.fire_and_forget(input);
+        if let Err(e) = trace::instrument(responding, &span).await {
             error!("respond fire_and_forget failed: {:?}", e);
+            trace::error(&span, &e);
         }
     }
 
     #[inline]
-    async fn on_request_response(&mut self, sid: u32, _flag: u16, input: Payload) {
+    async fn on_request_response(
+        &mut self,
+        sid: u32,
+        _flag: u16,
+        input: Payload,
+        permit: StreamPermit,
+    ) {
         let responder = self.responder.clone();
         let canceller = self.canceller.clone();
         let mut tx = self.tx.clone();
         let splitter = self.splitter.clone();
-        let counter = Counter::new(2);
-        self.register_handler(sid, Handler::ResRR(counter.clone()))
-            .await;
-        runtime::spawn(async move {
-            // TODO: use future select
-            let result = responder.request_response(input).await;
-            if counter.count_down() == 0 {
+        let (abort, registration) = AbortHandle::new_pair();
+        self.register_handler(sid, Handler::ResRR(abort)).await;
+        let span = self.stream_span(sid, Side::Responder, Interaction::RequestResponse);
+        let failed = span.clone();
+        let task = async move {
+            let _permit = permit;
+            let result = match Abortable::new(responder.request_response(input), registration).await
+            {
+                Ok(it) => it,
                 // cancelled
-                return;
-            }
+                Err(_) => return,
+            };
 
             // async remove canceller
-            canceller.send(sid).await.expect("Send canceller failed");
+            if let Err(e) = canceller.send(sid).await {
+                error!("send canceller failed: {}", e);
+            }
 
             match result {
                 Ok(Some(res)) => {
-                    Self::try_send_payload(
-                        &splitter,
-                        &mut tx,
-                        sid,
-                        res,
-                        Frame::FLAG_NEXT | Frame::FLAG_COMPLETE,
-                    )
-                    .await;
+                    let flag = Frame::FLAG_NEXT | Frame::FLAG_COMPLETE;
+                    if let Err(e) = Self::try_send_payload(&splitter, &mut tx, sid, res, flag).await
+                    {
+                        error!("respond REQUEST_RESPONSE failed: {}", e);
+                    }
                 }
                 Ok(None) => {
                     Self::try_send_complete(&mut tx, sid, Frame::FLAG_COMPLETE).await;
                 }
                 Err(e) => {
-                    let sending = frame::Error::builder(sid, 0)
-                        .set_code(error::ERR_APPLICATION)
-                        .set_data(Bytes::from(e.to_string()))
-                        .build();
+                    trace::error(&failed, &e);
+                    let sending = Self::application_error(&splitter, sid, &e);
                     if let Err(e) = tx.send(sending) {
                         error!("respond REQUEST_RESPONSE failed: {}", e);
                     }
                 }
             };
-        });
+        };
+        runtime::spawn(trace::instrument(task, &span));
     }
 
     #[inline]
-    async fn on_request_stream(&self, sid: u32, flag: u16, input: Payload) {
+    async fn on_request_stream(
+        &self,
+        sid: u32,
+        flag: u16,
+        initial_n: u32,
+        input: Payload,
+        permit: StreamPermit,
+    ) {
         let responder = self.responder.clone();
+        let canceller = self.canceller.clone();
         let mut tx = self.tx.clone();
         let splitter = self.splitter.clone();
-        runtime::spawn(async move {
-            // TODO: support cancel
+        let (credits_tx, mut credits_rx) = mpsc::unbounded_channel::<u32>();
+        let (abort, registration) = AbortHandle::new_pair();
+        self.register_handler(sid, Handler::ResRS(credits_tx, abort))
+            .await;
+        let span = self.stream_span(sid, Side::Responder, Interaction::RequestStream);
+        let failed = span.clone();
+        let task = async move {
             let mut payloads = responder.request_stream(input);
-            while let Some(next) = payloads.next().await {
-                match next {
-                    Ok(it) => {
-                        Self::try_send_payload(&splitter, &mut tx, sid, it, Frame::FLAG_NEXT).await;
+            let mut credits = Credits::new(initial_n);
+            loop {
+                match payloads.next().await {
+                    Some(Ok(it)) => {
+                        // hold the item until the requester grants more credits, terminal
+                        // signals need none.
+                        if !credits.acquire(&mut credits_rx).await {
+                            return;
+                        }
+                        let flag = Frame::FLAG_NEXT;
+                        if let Err(e) =
+                            Self::try_send_payload(&splitter, &mut tx, sid, it, flag).await
+                        {
+                            // the connection is gone, stop pulling the upstream.
+                            error!("respond REQUEST_STREAM failed: {}", e);
+                            break;
+                        }
                     }
-                    Err(e) => {
-                        let sending = frame::Error::builder(sid, 0)
-                            .set_code(error::ERR_APPLICATION)
-                            .set_data(Bytes::from(format!("{}", e)))
-                            .build();
-                        tx.send(sending).expect("Send stream response failed");
+                    Some(Err(e)) => {
+                        trace::error(&failed, &e);
+                        let sending = Self::application_error(&splitter, sid, &e);
+                        if let Err(e) = tx.send(sending) {
+                            error!("respond REQUEST_STREAM failed: {}", e);
+                        }
+                        break;
                     }
-                };
+                    None => {
+                        Self::try_send_complete(&mut tx, sid, Frame::FLAG_COMPLETE).await;
+                        break;
+                    }
+                }
             }
-            let complete = frame::Payload::builder(sid, Frame::FLAG_COMPLETE).build();
-            tx.send(complete)
-                .expect("Send stream complete response failed");
+            if let Err(e) = canceller.send(sid).await {
+                error!("send canceller failed: {}", e);
+            }
+        };
+        let task = trace::instrument(task, &span);
+        runtime::spawn(async move {
+            let _permit = permit;
+            // the responder's stream will be dropped once CANCEL arrives.
+            let _ = Abortable::new(task, registration).await;
         });
     }
 
     #[inline]
-    async fn on_request_channel(&self, sid: u32, flag: u16, first: Payload) {
+    async fn on_request_channel(
+        &self,
+        sid: u32,
+        flag: u16,
+        initial_n: u32,
+        first: Payload,
+        permit: StreamPermit,
+    ) {
         let responder = self.responder.clone();
+        let handlers = self.handlers.clone();
         let tx = self.tx.clone();
-        let (sender, mut receiver) = mpsc::channel::<Result<Payload>>(32);
-        sender.send(Ok(first)).await.expect("Send failed!");
-        self.register_handler(sid, Handler::ReqRC(sender)).await;
+        let splitter = self.splitter.clone();
+        let complete = flag & Frame::FLAG_COMPLETE != 0;
+        let (sender, receiver) = mpsc::unbounded_channel::<Result<Payload>>();
+        // an empty REQUEST_CHANNEL with COMPLETE means the requester has nothing to send.
+        if !(complete && first.is_empty()) {
+            // the receiver is held below, so the first payload can always be sent.
+            let _ = sender.send(Ok(first));
+        }
+        let (credits_tx, credits_rx) = mpsc::unbounded_channel::<u32>();
+        let (abort, registration) = AbortHandle::new_pair();
+        let channel = Channel {
+            inbound: if complete { None } else { Some(sender) },
+            outbound: Some((credits_tx, abort)),
+        };
+        self.register_handler(sid, Handler::ResRC(channel)).await;
+        // the first payload is granted by REQUEST_CHANNEL itself.
+        let outstanding = if complete { REQUEST_MAX } else { 1 };
+        let inputs = self.replenish(sid, receiver, self.limit_rate, outstanding, None);
+        let span = self.stream_span(sid, Side::Responder, Interaction::RequestChannel);
+        let failed = span.clone();
+        let task = async move {
+            let recorded = failed.clone();
+            let outputs = responder.request_channel(inputs).inspect(move |it| {
+                if let Err(e) = it {
+                    trace::error(&recorded, e);
+                }
+            });
+            let sent = Self::send_outbound(
+                &handlers,
+                tx,
+                &splitter,
+                sid,
+                Box::pin(outputs),
+                Credits::new(initial_n),
+                credits_rx,
+            )
+            .await;
+            if let Err(e) = sent {
+                trace::error(&failed, &e);
+            }
+        };
+        let task = trace::instrument(task, &span);
         runtime::spawn(async move {
-            // respond client channel
-            let mut outputs = responder.request_channel(Box::pin(stream! {
-                while let Some(it) = receiver.recv().await{
-                    yield it;
-                }
-            }));
-            // TODO: support custom RequestN.
-            let request_n = frame::RequestN::builder(sid, 0).build();
-
-            if let Err(e) = tx.send(request_n) {
-                error!("respond REQUEST_N failed: {}", e);
-            }
-
-            while let Some(next) = outputs.next().await {
-                let sending = match next {
-                    Ok(payload) => {
-                        let (data, metadata) = payload.split();
-                        let mut bu = frame::Payload::builder(sid, Frame::FLAG_NEXT);
-                        if let Some(b) = data {
-                            bu = bu.set_data(b);
-                        }
-                        if let Some(b) = metadata {
-                            bu = bu.set_metadata(b);
-                        }
-                        bu.build()
+            let _permit = permit;
+            // the responder's stream will be dropped once CANCEL arrives.
+            let _ = Abortable::new(task, registration).await;
+        });
+    }
+
+    /// Sends the outbound half of a channel while the peer grants credits, an error of the outbound
+    /// terminates both halves of the channel. Returns the error if the connection is gone.
+    async fn send_outbound(
+        handlers: &Handlers,
+        mut tx: mpsc::UnboundedSender<Frame>,
+        splitter: &Option<Splitter>,
+        sid: u32,
+        mut outputs: Flux<Result<Payload>>,
+        mut credits: Credits,
+        mut credits_rx: mpsc::UnboundedReceiver<u32>,
+    ) -> Result<()> {
+        loop {
+            match outputs.next().await {
+                Some(Ok(it)) => {
+                    // hold the item until the peer grants more credits, terminal signals need
+                    // none.
+                    if !credits.acquire(&mut credits_rx).await {
+                        return Ok(());
                     }
-                    Err(e) => frame::Error::builder(sid, 0)
-                        .set_code(error::ERR_APPLICATION)
-                        .set_data(Bytes::from(format!("{}", e)))
-                        .build(),
-                };
-                tx.send(sending).expect("Send failed!");
+                    // the connection is gone, stop pulling the outbound.
+                    Self::try_send_payload(splitter, &mut tx, sid, it, Frame::FLAG_NEXT).await?;
+                }
+                Some(Err(e)) => {
+                    Self::fail_channel(handlers, &tx, splitter, sid, e);
+                    return Ok(());
+                }
+                None => {
+                    Self::try_send_complete(&mut tx, sid, Frame::FLAG_COMPLETE).await;
+                    Self::release_outbound(handlers, sid);
+                    return Ok(());
+                }
             }
-            let complete = frame::Payload::builder(sid, Frame::FLAG_COMPLETE).build();
-            if let Err(e) = tx.send(complete) {
-                error!("complete REQUEST_CHANNEL failed: {}", e);
+        }
+    }
+
+    fn release_outbound(handlers: &Handlers, sid: u32) {
+        if let Entry::Occupied(mut o) = handlers.entry(sid) {
+            if let Handler::ReqRC(c) | Handler::ResRC(c) = o.get_mut() {
+                c.outbound = None;
+                if c.inbound.is_none() {
+                    o.remove();
+                }
             }
-        });
+        }
+        handlers.release();
+    }
+
+    /// Terminates both directions of a channel whose outbound failed locally.
+    fn fail_channel(
+        handlers: &Handlers,
+        tx: &mpsc::UnboundedSender<Frame>,
+        splitter: &Option<Splitter>,
+        sid: u32,
+        e: anyhow::Error,
+    ) {
+        let sending = Self::application_error(splitter, sid, &e);
+        if let Err(e) = tx.send(sending) {
+            error!("send REQUEST_CHANNEL failed: {}", e);
+        }
+        if let Some((_, Handler::ReqRC(c))) | Some((_, Handler::ResRC(c))) = handlers.remove(&sid) {
+            // the local inbound receives the error as it is, e.g. with the code of a custom error.
+            if let Some(inbound) = c.inbound {
+                if inbound.send(Err(e)).is_err() {
+                    debug!("REQUEST_CHANNEL {} has been dropped", sid);
+                }
+            }
+        }
     }
 
     #[inline]
     async fn on_metadata_push(&mut self, input: Payload) {
         if let Err(e) = self.responder.metadata_push(input).await {
             error!("response metadata_push failed: {:?}", e);
+            trace::error(&self.span, &e);
         }
     }
 
@@ -520,67 +1268,6 @@ impl DuplexSocket {
         }
     }
 
-    #[inline]
-    async fn try_send_channel(
-        splitter: &Option<Splitter>,
-        tx: &mut mpsc::UnboundedSender<Frame>,
-        sid: u32,
-        res: Payload,
-        flag: u16,
-    ) {
-        // TODO
-        match splitter {
-            Some(sp) => {
-                let mut cuts: usize = 0;
-                let mut prev: Option<Payload> = None;
-                for next in sp.cut(res, 4) {
-                    if let Some(cur) = prev.take() {
-                        let sending = if cuts == 1 {
-                            frame::RequestChannel::builder(sid, flag | Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        } else {
-                            frame::Payload::builder(sid, Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        };
-                        // send frame
-                        if let Err(e) = tx.send(sending) {
-                            error!("send request_channel failed: {}", e);
-                            return;
-                        }
-                    }
-                    prev = Some(next);
-                    cuts += 1;
-                }
-
-                let sending = if cuts == 0 {
-                    frame::RequestChannel::builder(sid, flag).build()
-                } else if cuts == 1 {
-                    frame::RequestChannel::builder(sid, flag)
-                        .set_all(prev.unwrap().split())
-                        .build()
-                } else {
-                    frame::Payload::builder(sid, 0)
-                        .set_all(prev.unwrap().split())
-                        .build()
-                };
-                // send frame
-                if let Err(e) = tx.send(sending) {
-                    error!("send request_channel failed: {}", e);
-                }
-            }
-            None => {
-                let sending = frame::RequestChannel::builder(sid, flag)
-                    .set_all(res.split())
-                    .build();
-                if let Err(e) = tx.send(sending) {
-                    error!("send request_channel failed: {}", e);
-                }
-            }
-        }
-    }
-
     #[inline]
     async fn try_send_complete(tx: &mut mpsc::UnboundedSender<Frame>, sid: u32, flag: u16) {
         let sending = frame::Payload::builder(sid, flag).build();
@@ -596,311 +1283,496 @@ impl DuplexSocket {
         sid: u32,
         res: Payload,
         flag: u16,
-    ) {
-        match splitter {
-            Some(sp) => {
-                let mut cuts: usize = 0;
-                let mut prev: Option<Payload> = None;
-                for next in sp.cut(res, 0) {
-                    if let Some(cur) = prev.take() {
-                        let sending = if cuts == 1 {
-                            frame::Payload::builder(sid, flag | Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        } else {
-                            frame::Payload::builder(sid, Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        };
-                        // send frame
-                        if let Err(e) = tx.send(sending) {
-                            error!("send payload failed: {}", e);
-                            return;
-                        }
-                    }
-                    prev = Some(next);
-                    cuts += 1;
-                }
+    ) -> Result<()> {
+        Self::send_fragmented(splitter, tx, sid, flag, res, 0, |flag, it| {
+            frame::Payload::builder(sid, flag)
+                .set_all(it.split())
+                .build()
+        })
+    }
 
-                let sending = if cuts == 0 {
-                    frame::Payload::builder(sid, flag).build()
-                } else {
-                    frame::Payload::builder(sid, flag)
-                        .set_all(prev.unwrap().split())
-                        .build()
+    /// Builds the ERROR frame of a responder error, custom errors keep their code and data.
+    fn application_error(splitter: &Option<Splitter>, sid: u32, e: &anyhow::Error) -> Frame {
+        if let Some(RSocketError::Custom(code, data)) = e.downcast_ref::<RSocketError>() {
+            if RSocketError::is_custom_code(*code) {
+                let data = match splitter {
+                    Some(sp) => sp.fit_error_data(data),
+                    None => data.clone(),
                 };
-                // send frame
-                if let Err(e) = tx.send(sending) {
-                    error!("send payload failed: {}", e);
-                }
-            }
-            None => {
-                let sending = frame::Payload::builder(sid, flag)
-                    .set_all(res.split())
+                return frame::Error::builder(sid, 0)
+                    .set_code(*code)
+                    .set_data(data)
                     .build();
-                if let Err(e) = tx.send(sending) {
-                    error!("respond failed: {}", e);
-                }
             }
         }
+        let desc = e.to_string();
+        let desc = match splitter {
+            Some(sp) => sp.fit_error(&desc),
+            None => &desc,
+        };
+        frame::Error::builder(sid, 0)
+            .set_code(error::ERR_APPLICATION)
+            .set_data(Bytes::from(desc.to_owned()))
+            .build()
     }
-}
 
-#[async_trait]
-impl RSocket for DuplexSocket {
-    async fn metadata_push(&self, req: Payload) -> Result<()> {
-        let sid = self.seq.next();
-        let tx = self.tx.clone();
-        let (_d, m) = req.split();
-        let mut bu = frame::MetadataPush::builder(sid, 0);
-        if let Some(b) = m {
-            bu = bu.set_metadata(b);
+    /// Sends a payload in the frame built by `head`, or in fragments if it exceeds the MTU:
+    /// `head` builds the first fragment, the rest are PAYLOAD frames, and every fragment but the
+    /// last one has FOLLOW. `skip` is the size of the fields preceding the payload in `head`.
+    fn send_fragmented<F>(
+        splitter: &Option<Splitter>,
+        tx: &mpsc::UnboundedSender<Frame>,
+        sid: u32,
+        flag: u16,
+        payload: Payload,
+        skip: usize,
+        head: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(u16, Payload) -> Frame,
+    {
+        let sp = match splitter {
+            Some(sp) => sp,
+            None => {
+                tx.send(head(flag, payload))?;
+                return Ok(());
+            }
+        };
+        let mut fragments = sp.cut(payload, skip).peekable();
+        let first = fragments.next().unwrap_or_else(|| Payload::new(None, None));
+        if fragments.peek().is_none() {
+            tx.send(head(flag, first))?;
+            return Ok(());
+        }
+        tx.send(head(flag | Frame::FLAG_FOLLOW, first))?;
+        while let Some(next) = fragments.next() {
+            let flag = if fragments.peek().is_some() {
+                Frame::FLAG_FOLLOW
+            } else {
+                flag & (Frame::FLAG_NEXT | Frame::FLAG_COMPLETE)
+            };
+            let sending = frame::Payload::builder(sid, flag)
+                .set_all(next.split())
+                .build();
+            tx.send(sending)?;
         }
-        tx.send(bu.build())?;
         Ok(())
     }
 
-    async fn fire_and_forget(&self, req: Payload) -> Result<()> {
-        let sid = self.seq.next();
-        let tx = self.tx.clone();
-        let splitter = self.splitter.clone();
-
-        match splitter {
-            Some(sp) => {
-                let mut cuts: usize = 0;
-                let mut prev: Option<Payload> = None;
-                for next in sp.cut(req, 0) {
-                    if let Some(cur) = prev.take() {
-                        let sending = if cuts == 1 {
-                            // make first frame as request_fnf.
-                            frame::RequestFNF::builder(sid, Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        } else {
-                            // make other frames as payload.
-                            frame::Payload::builder(sid, Frame::FLAG_FOLLOW)
-                                .set_all(cur.split())
-                                .build()
-                        };
-                        // send frame
-                        tx.send(sending)?;
+    fn request_stream_with(
+        &self,
+        input: Payload,
+        limit_rate: LimitRate,
+        timeout: Option<Duration>,
+    ) -> Flux<Result<Payload>> {
+        match self.requesting.clone() {
+            Some(limit) => {
+                let socket = self.clone();
+                // the request is sent once a permit is acquired, the permit is held until the
+                // stream terminates.
+                Box::pin(stream! {
+                    let _permit = match limit.acquire().await {
+                        Ok(it) => it,
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    };
+                    let mut results = socket.send_request_stream(input, limit_rate, timeout);
+                    while let Some(next) = results.next().await {
+                        yield next;
                     }
-                    prev = Some(next);
-                    cuts += 1;
-                }
-
-                let sending = if cuts == 0 {
-                    frame::RequestFNF::builder(sid, 0).build()
-                } else if cuts == 1 {
-                    frame::RequestFNF::builder(sid, 0)
-                        .set_all(prev.unwrap().split())
-                        .build()
-                } else {
-                    frame::Payload::builder(sid, 0)
-                        .set_all(prev.unwrap().split())
-                        .build()
-                };
-                // send frame
-                tx.send(sending)?;
-            }
-            None => {
-                let sending = frame::RequestFNF::builder(sid, 0)
-                    .set_all(req.split())
-                    .build();
-                tx.send(sending)?;
+                })
             }
+            None => self.send_request_stream(input, limit_rate, timeout),
         }
-        Ok(())
     }
 
-    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
-        let (tx, rx) = oneshot::channel::<Result<Option<Payload>>>();
-        let sid = self.seq.next();
+    fn send_request_stream(
+        &self,
+        input: Payload,
+        limit_rate: LimitRate,
+        timeout: Option<Duration>,
+    ) -> Flux<Result<Payload>> {
+        if let Err(e) = self.admit_request() {
+            return Box::pin(futures::stream::once(async { Err(e) }));
+        }
+        let sid = match self.next_stream_id() {
+            Ok(it) => it,
+            Err(e) => return Box::pin(futures::stream::once(async { Err(e) })),
+        };
+        let tx = self.tx.clone();
+        let initial_n = limit_rate.high_tide();
+        // register handler
+        let (sender, receiver) = mpsc::unbounded_channel::<Result<Payload>>();
+        self.handlers.insert(sid, Handler::ReqRS(sender));
         let handlers = self.handlers.clone();
-        let sender = self.tx.clone();
-
         let splitter = self.splitter.clone();
+        let span = self.stream_span(sid, Side::Requester, Interaction::RequestStream);
+        let task = async move {
+            // hold the handler while sending, CANCEL must not overtake the request.
+            let _registered = match handlers.get(&sid) {
+                Some(it) => it,
+                None => return,
+            };
+            // skip 4 bytes. (initial_request_n is u32)
+            let sent = Self::send_fragmented(&splitter, &tx, sid, 0, input, 4, |flag, it| {
+                frame::RequestStream::builder(sid, flag)
+                    .set_initial_request_n(initial_n)
+                    .set_all(it.split())
+                    .build()
+            });
+            if let Err(e) = sent {
+                error!("send request_stream failed: {}", e);
+            }
+        };
+        runtime::spawn(trace::instrument(task, &span));
+        let results = self.replenish(sid, receiver, limit_rate, initial_n, timeout);
+        let active = self.track(Side::Requester, Interaction::RequestStream);
+        Self::observe(results, span, active)
+    }
 
-        runtime::spawn(async move {
-            // register handler
-            handlers.insert(sid, Handler::ReqRR(tx));
-            match splitter {
-                Some(sp) => {
-                    let mut cuts: usize = 0;
-                    let mut prev: Option<Payload> = None;
-                    for next in sp.cut(req, 0) {
-                        if let Some(cur) = prev.take() {
-                            let sending = if cuts == 1 {
-                                // make first frame as request_response.
-                                frame::RequestResponse::builder(sid, Frame::FLAG_FOLLOW)
-                                    .set_all(cur.split())
-                                    .build()
-                            } else {
-                                // make other frames as payload.
-                                frame::Payload::builder(sid, Frame::FLAG_FOLLOW)
-                                    .set_all(cur.split())
-                                    .build()
-                            };
-                            // send frame
-                            if let Err(e) = sender.send(sending) {
-                                error!("send request_response failed: {}", e);
-                                return;
-                            }
+    fn request_channel_with(
+        &self,
+        reqs: Flux<Result<Payload>>,
+        limit_rate: LimitRate,
+        timeout: Option<Duration>,
+    ) -> Flux<Result<Payload>> {
+        match self.requesting.clone() {
+            Some(limit) => {
+                let socket = self.clone();
+                Box::pin(stream! {
+                    let _permit = match limit.acquire().await {
+                        Ok(it) => it,
+                        Err(e) => {
+                            yield Err(e);
+                            return;
                         }
-                        prev = Some(next);
-                        cuts += 1;
-                    }
-
-                    let sending = if cuts == 0 {
-                        frame::RequestResponse::builder(sid, 0).build()
-                    } else if cuts == 1 {
-                        frame::RequestResponse::builder(sid, 0)
-                            .set_all(prev.unwrap().split())
-                            .build()
-                    } else {
-                        frame::Payload::builder(sid, 0)
-                            .set_all(prev.unwrap().split())
-                            .build()
                     };
-                    // send frame
-                    if let Err(e) = sender.send(sending) {
-                        error!("send request_response failed: {}", e);
-                    }
-                }
-                None => {
-                    // crate request frame
-                    let sending = frame::RequestResponse::builder(sid, 0)
-                        .set_all(req.split())
-                        .build();
-                    // send frame
-                    if let Err(e) = sender.send(sending) {
-                        error!("send request_response failed: {}", e);
+                    let mut results = socket.send_request_channel(reqs, limit_rate, timeout);
+                    while let Some(next) = results.next().await {
+                        yield next;
                     }
-                }
+                })
             }
-        });
-        match rx.await {
-            Ok(v) => v,
-            Err(_e) => Err(RSocketError::WithDescription("request_response failed".into()).into()),
+            None => self.send_request_channel(reqs, limit_rate, timeout),
         }
     }
 
-    fn request_stream(&self, input: Payload) -> Flux<Result<Payload>> {
-        let sid = self.seq.next();
+    fn send_request_channel(
+        &self,
+        mut reqs: Flux<Result<Payload>>,
+        limit_rate: LimitRate,
+        timeout: Option<Duration>,
+    ) -> Flux<Result<Payload>> {
+        if let Err(e) = self.admit_request() {
+            return Box::pin(futures::stream::once(async { Err(e) }));
+        }
+        let sid = match self.next_stream_id() {
+            Ok(it) => it,
+            Err(e) => return Box::pin(futures::stream::once(async { Err(e) })),
+        };
         let tx = self.tx.clone();
-        // register handler
-        let (sender, mut receiver) = mpsc::channel::<Result<Payload>>(32);
+        let initial_n = limit_rate.high_tide();
+        let (sender, receiver) = mpsc::unbounded_channel::<Result<Payload>>();
+        let (credits_tx, credits_rx) = mpsc::unbounded_channel::<u32>();
+        let (abort, registration) = AbortHandle::new_pair();
         let handlers = self.handlers.clone();
         let splitter = self.splitter.clone();
-        runtime::spawn(async move {
-            handlers.insert(sid, Handler::ReqRS(sender));
-            match splitter {
-                Some(sp) => {
-                    let mut cuts: usize = 0;
-                    let mut prev: Option<Payload> = None;
-                    // skip 4 bytes. (initial_request_n is u32)
-                    for next in sp.cut(input, 4) {
-                        if let Some(cur) = prev.take() {
-                            let sending: Frame = if cuts == 1 {
-                                // make first frame as request_stream.
-                                frame::RequestStream::builder(sid, Frame::FLAG_FOLLOW)
-                                    .set_all(cur.split())
-                                    .build()
-                            } else {
-                                // make other frames as payload.
-                                frame::Payload::builder(sid, Frame::FLAG_FOLLOW)
-                                    .set_all(cur.split())
-                                    .build()
-                            };
-                            // send frame
-                            if let Err(e) = tx.send(sending) {
-                                error!("send request_stream failed: {}", e);
-                                return;
-                            }
-                        }
-                        prev = Some(next);
-                        cuts += 1;
+        let task = async move {
+            // the first payload is carried by REQUEST_CHANNEL, an empty requester completes it.
+            let (first, flag) = match reqs.next().await {
+                Some(Ok(it)) => (it, 0),
+                Some(Err(e)) => {
+                    // nothing has been sent yet, just fail the inbound.
+                    if sender.send(Err(e)).is_err() {
+                        debug!("REQUEST_CHANNEL {} has been dropped", sid);
                     }
-
-                    let sending = if cuts == 0 {
-                        frame::RequestStream::builder(sid, 0).build()
-                    } else if cuts == 1 {
-                        frame::RequestStream::builder(sid, 0)
-                            .set_all(prev.unwrap().split())
-                            .build()
-                    } else {
-                        frame::Payload::builder(sid, 0)
-                            .set_all(prev.unwrap().split())
-                            .build()
-                    };
-                    // send frame
-                    if let Err(e) = tx.send(sending) {
-                        error!("send request_stream failed: {}", e);
+                    return;
+                }
+                None => (Payload::builder().build(), Frame::FLAG_COMPLETE),
+            };
+            let complete = flag & Frame::FLAG_COMPLETE != 0;
+            {
+                // register while sending, CANCEL must not overtake the request.
+                let vacant = match handlers.entry(sid) {
+                    Entry::Vacant(it) => it,
+                    Entry::Occupied(_) => {
+                        error!("duplicated stream id {}", sid);
+                        return;
                     }
+                };
+                // skip 4 bytes. (initial_request_n is u32)
+                let sent =
+                    Self::send_fragmented(&splitter, &tx, sid, flag, first, 4, |flag, it| {
+                        frame::RequestChannel::builder(sid, flag)
+                            .set_initial_request_n(initial_n)
+                            .set_all(it.split())
+                            .build()
+                    });
+                if let Err(e) = sent {
+                    error!("send request_channel failed: {}", e);
+                    return;
                 }
-                None => {
-                    let sending = frame::RequestStream::builder(sid, 0)
-                        .set_all(input.split())
-                        .build();
+                let inbound = if sender.is_closed() {
+                    // the requester has given up the inbound already.
+                    let sending = frame::Cancel::builder(sid, 0).build();
                     if let Err(e) = tx.send(sending) {
-                        error!("send request_stream failed: {}", e);
+                        error!("send CANCEL failed: {}", e);
                     }
+                    None
+                } else {
+                    Some(sender)
+                };
+                let outbound = if complete {
+                    None
+                } else {
+                    Some((credits_tx, abort))
+                };
+                if inbound.is_some() || outbound.is_some() {
+                    vacant.insert(Handler::ReqRC(Channel { inbound, outbound }));
                 }
             }
+            if !complete {
+                // a failed outbound is delivered to the inbound as well.
+                let _ = Self::send_outbound(
+                    &handlers,
+                    tx,
+                    &splitter,
+                    sid,
+                    reqs,
+                    Credits::new(0),
+                    credits_rx,
+                )
+                .await;
+            }
+        };
+        let span = self.stream_span(sid, Side::Requester, Interaction::RequestChannel);
+        let task = trace::instrument(task, &span);
+        runtime::spawn(async move {
+            // the outbound will be dropped once CANCEL arrives.
+            let _ = Abortable::new(task, registration).await;
         });
+        let results = self.replenish(sid, receiver, limit_rate, initial_n, timeout);
+        let active = self.track(Side::Requester, Interaction::RequestChannel);
+        Self::observe(results, span, active)
+    }
+
+    /// Records the errors of a requested stream in its span, and closes it in the metrics once
+    /// the Flux is dropped.
+    fn observe(
+        mut results: Flux<Result<Payload>>,
+        span: Span,
+        active: Option<ActiveStream>,
+    ) -> Flux<Result<Payload>> {
         Box::pin(stream! {
-            while let Some(it) = receiver.recv().await{
-                yield it;
+            let _active = active;
+            while let Some(next) = results.next().await {
+                if let Err(e) = &next {
+                    trace::error(&span, e);
+                }
+                yield next;
             }
         })
     }
 
-    fn request_channel(&self, mut reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
-        let sid = self.seq.next();
-        let mut tx = self.tx.clone();
-        // register handler
-        let (sender, mut receiver) = mpsc::channel::<Result<Payload>>(32);
-        let handlers = self.handlers.clone();
-        let splitter = self.splitter.clone();
-        runtime::spawn(async move {
-            handlers.insert(sid, Handler::ReqRC(sender));
-            let mut first = true;
-            while let Some(next) = reqs.next().await {
-                match next {
-                    Ok(it) => {
-                        if first {
-                            first = false;
-                            Self::try_send_channel(&splitter, &mut tx, sid, it, Frame::FLAG_NEXT)
-                                .await
-                        } else {
-                            Self::try_send_payload(&splitter, &mut tx, sid, it, Frame::FLAG_NEXT)
-                                .await
-                        }
-                    }
-                    Err(e) => {
-                        let sending = frame::Error::builder(sid, 0)
-                            .set_code(error::ERR_APPLICATION)
-                            .set_data(Bytes::from(format!("{}", e)))
-                            .build();
-                        if let Err(e) = tx.send(sending) {
-                            error!("send REQUEST_CHANNEL failed: {}", e);
+    fn cancel_guard(&self, sid: u32) -> CancelGuard {
+        CancelGuard {
+            sid,
+            tx: self.tx.clone(),
+            handlers: self.handlers.clone(),
+        }
+    }
+
+    /// Wraps inbound payloads as a Flux which replenishes REQUEST_N while being consumed,
+    /// `outstanding` is the demand which has been granted to the peer already.
+    /// CANCEL is sent if the Flux is dropped before it terminates.
+    fn replenish(
+        &self,
+        sid: u32,
+        mut receiver: mpsc::UnboundedReceiver<Result<Payload>>,
+        limit_rate: LimitRate,
+        mut outstanding: u32,
+        timeout: Option<Duration>,
+    ) -> Flux<Result<Payload>> {
+        let tx = self.tx.clone();
+        let guard = self.cancel_guard(sid);
+        let deadline = timeout.map(|it| (Instant::now() + it, it));
+        Box::pin(stream! {
+            // the guard sends CANCEL once the stream is dropped or expires.
+            let _guard = guard;
+            loop {
+                let next = match deadline {
+                    Some((deadline, timeout)) => {
+                        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                            Ok(it) => it,
+                            Err(_) => {
+                                yield Err(RSocketError::RequestTimeout(timeout).into());
+                                break;
+                            }
                         }
                     }
+                    None => receiver.recv().await,
+                };
+                let it = match next {
+                    Some(it) => it,
+                    None => break,
                 };
-            }
-            let sending = frame::Payload::builder(sid, Frame::FLAG_COMPLETE).build();
-            if let Err(e) = tx.send(sending) {
-                error!("complete REQUEST_CHANNEL failed: {}", e);
-            }
-        });
-        Box::pin(stream! {
-            while let Some(it) = receiver.recv().await{
                 yield it;
+                if outstanding == REQUEST_MAX {
+                    continue;
+                }
+                outstanding = outstanding.saturating_sub(1);
+                if outstanding <= limit_rate.low_tide() {
+                    let n = limit_rate.high_tide() - outstanding;
+                    let sending = frame::RequestN::builder(sid, 0).set_n(n).build();
+                    if let Err(e) = tx.send(sending) {
+                        error!("send REQUEST_N failed: {}", e);
+                        break;
+                    }
+                    outstanding = limit_rate.high_tide();
+                }
             }
         })
     }
 }
 
+#[async_trait]
+impl RSocket for DuplexSocket {
+    async fn metadata_push(&self, req: Payload) -> Result<()> {
+        self.count_request(Side::Requester, Interaction::MetadataPush);
+        let tx = self.tx.clone();
+        let (_d, m) = req.split();
+        let mut bu = frame::MetadataPush::builder(0, 0);
+        if let Some(b) = m {
+            bu = bu.set_metadata(b);
+        }
+        tx.send(bu.build())?;
+        Ok(())
+    }
+
+    async fn fire_and_forget(&self, req: Payload) -> Result<()> {
+        self.admit_request()?;
+        let sid = self.next_stream_id()?;
+        self.count_request(Side::Requester, Interaction::FireAndForget);
+        Self::send_fragmented(&self.splitter, &self.tx, sid, 0, req, 0, |flag, it| {
+            frame::RequestFNF::builder(sid, flag)
+                .set_all(it.split())
+                .build()
+        })?;
+        Ok(())
+    }
+
+    async fn request_response(&self, req: Payload) -> Result<Option<Payload>> {
+        self.request_response_with(req, self.timeout).await
+    }
+
+    fn request_stream(&self, input: Payload) -> Flux<Result<Payload>> {
+        self.request_stream_with(input, self.limit_rate, self.stream_timeout)
+    }
+
+    fn request_channel(&self, reqs: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        self.request_channel_with(reqs, self.limit_rate, self.stream_timeout)
+    }
+}
+
+impl DuplexSocket {
+    /// Request-Response which fails with `RSocketError::RequestTimeout` and sends CANCEL if no
+    /// response arrives within the timeout.
+    async fn request_response_with(
+        &self,
+        req: Payload,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Payload>> {
+        let _permit = match &self.requesting {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        self.admit_request()?;
+        let (tx, rx) = oneshot::channel::<Result<Option<Payload>>>();
+        let sid = self.next_stream_id()?;
+        let sender = self.tx.clone();
+        // register handler
+        self.handlers.insert(sid, Handler::ReqRR(tx));
+        let _guard = self.cancel_guard(sid);
+        let _active = self.track(Side::Requester, Interaction::RequestResponse);
+        let started = Instant::now();
+
+        let handlers = self.handlers.clone();
+        let splitter = self.splitter.clone();
+        let span = self.stream_span(sid, Side::Requester, Interaction::RequestResponse);
+
+        let task = async move {
+            // hold the handler while sending, CANCEL must not overtake the request.
+            let _registered = match handlers.get(&sid) {
+                Some(it) => it,
+                None => return,
+            };
+            let sent = Self::send_fragmented(&splitter, &sender, sid, 0, req, 0, |flag, it| {
+                frame::RequestResponse::builder(sid, flag)
+                    .set_all(it.split())
+                    .build()
+            });
+            if let Err(e) = sent {
+                error!("send request_response failed: {}", e);
+            }
+        };
+        runtime::spawn(trace::instrument(task, &span));
+        let res = match timeout {
+            // the guard sends CANCEL once the request expires.
+            Some(timeout) => match tokio::time::timeout(timeout, rx).await {
+                Ok(it) => it,
+                Err(_) => {
+                    let e = RSocketError::RequestTimeout(timeout);
+                    trace::error(&span, &e);
+                    return Err(e.into());
+                }
+            },
+            None => rx.await,
+        };
+        if let Some(recorder) = &self.metrics {
+            recorder.request_response_latency(started.elapsed());
+        }
+        let res = match res {
+            Ok(v) => v,
+            Err(_e) => Err(RSocketError::WithDescription("request_response failed".into()).into()),
+        };
+        if let Err(e) = &res {
+            trace::error(&span, e);
+        }
+        res
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        let cancelled = match self.handlers.entry(self.sid) {
+            Entry::Occupied(mut o) => match o.get_mut() {
+                Handler::ReqRC(c) | Handler::ResRC(c) => {
+                    // only the inbound is cancelled, the outbound keeps going.
+                    let cancelled = c.inbound.take().is_some();
+                    if c.outbound.is_none() {
+                        o.remove();
+                    }
+                    cancelled
+                }
+                _ => {
+                    o.remove();
+                    true
+                }
+            },
+            Entry::Vacant(_) => false,
+        };
+        self.handlers.release();
+        if cancelled {
+            let sending = frame::Cancel::builder(self.sid, 0).build();
+            if let Err(e) = self.tx.send(sending) {
+                debug!("send CANCEL {} failed: {}", self.sid, e);
+            }
+        }
+    }
+}
+
 impl From<Box<dyn RSocket>> for Responder {
     fn from(input: Box<dyn RSocket>) -> Responder {
         Responder {
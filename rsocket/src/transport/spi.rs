@@ -18,6 +18,21 @@ use crate::{Error, Result};
 pub type FrameSink = dyn Sink<Frame, Error = RSocketError> + Send + Unpin;
 pub type FrameStream = dyn Stream<Item = StdResult<Frame, RSocketError>> + Send + Unpin;
 
+/// The direction of a frame seen by a `FrameInterceptor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The frame has been received from the peer.
+    Inbound,
+    /// The frame is about to be sent to the peer.
+    Outbound,
+}
+
+/// Sees every frame sent or received by a connection, including SETUP, RESUME and KEEPALIVE.
+pub trait FrameInterceptor: Send + Sync {
+    /// Returns the frame to pass on, which may be modified, or None to drop it.
+    fn intercept(&self, direction: Direction, frame: Frame) -> Option<Frame>;
+}
+
 pub trait Connection {
     fn split(self) -> (Box<FrameSink>, Box<FrameStream>);
 }
@@ -27,6 +42,11 @@ pub trait Transport {
     type Conn: Connection + Send;
 
     async fn connect(self) -> Result<Self::Conn>;
+
+    /// Describes the peer for diagnostics, e.g. its address.
+    fn peer(&self) -> Option<String> {
+        None
+    }
 }
 
 #[async_trait]
@@ -47,7 +47,9 @@ impl RSocket for EchoRSocket {
         runtime::spawn(async move {
             while let Some(it) = reqs.next().await {
                 info!("{:?}", it);
-                sender.send(it).unwrap();
+                if sender.send(it).is_err() {
+                    break;
+                }
             }
         });
         Box::pin(stream! {